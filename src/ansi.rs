@@ -0,0 +1,572 @@
+//! Writing a document for reading in a terminal, with colours and hyperlinks.
+
+use std::{borrow::Cow, collections::HashMap, io};
+
+use crate::{
+    format::{display_width, indented_code},
+    parser::{block::Block, util::MarkdownText},
+    plain::{image_target, link_target, link_targets, strip_markup},
+    render::{RenderOptions, Renderer},
+    slug::plain_text,
+    toc::TocEntry,
+    Markdown,
+};
+
+/// Settings for writing a [`Markdown`] document to a terminal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnsiOptions {
+    /// Wrap text to this many columns, or don't wrap at all
+    pub width: Option<usize>,
+    /// Use escape codes for colours, text styles and hyperlinks.  Without them, the target of
+    /// each link is written after its text instead.
+    pub color: bool,
+}
+
+impl Default for AnsiOptions {
+    fn default() -> Self {
+        Self {
+            width: Some(80),
+            color: true,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+struct Style {
+    bold: bool,
+    underline: bool,
+    code: bool,
+    link: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+struct Span {
+    text: String,
+    style: Style,
+}
+
+/// A run of text that's laid out on its own: a paragraph, or an item in a list.
+struct Item {
+    /// Written before the first line
+    marker: String,
+    /// Written before every other line
+    indent: usize,
+    /// The words to lay out, where an empty word is a line break that has to be kept
+    words: Vec<Vec<Span>>,
+}
+
+impl<'source> Markdown<'source> {
+    /// Write this document for reading in a terminal.
+    ///
+    /// Headings are bold and underlined, inline code is coloured, links become terminal
+    /// hyperlinks, list items are indented under their bullet and everything else but indented
+    /// code is wrapped to [`AnsiOptions::width`].  Control characters in the document are
+    /// dropped, so it can't send escape codes of its own.
+    pub fn write_ansi<Writer: io::Write>(
+        &self,
+        output: &mut Writer,
+        options: &AnsiOptions,
+    ) -> io::Result<()> {
+        self.render(options, output, &RenderOptions::default())
+    }
+
+    /// Write this document for a terminal, following the settings every format shares.
+    fn render_ansi<Writer: io::Write>(
+        &self,
+        output: &mut Writer,
+        options: &AnsiOptions,
+        render_options: &RenderOptions,
+    ) -> io::Result<()> {
+        let definitions = link_targets(&self.blocks);
+
+        let mut first = true;
+        for block in self.blocks.iter() {
+            let items = match block {
+                Block::Paragraph(p) => match indented_code(&p.text) {
+                    Some(lines) => {
+                        if !first {
+                            writeln!(output)?;
+                        }
+                        write_code(output, &lines, options)?;
+                        first = false;
+                        continue;
+                    }
+                    None => items(spans(
+                        &p.text,
+                        &definitions,
+                        options,
+                        render_options,
+                        &Style::default(),
+                    )),
+                },
+                Block::Heading(h) => {
+                    let style = Style {
+                        bold: true,
+                        underline: true,
+                        ..Default::default()
+                    };
+                    let mut spans = spans(h.text(), &definitions, options, render_options, &style);
+                    spans
+                        .iter_mut()
+                        .for_each(|s| s.text = s.text.replace('\n', " "));
+                    items(spans)
+                }
+                Block::Toc(toc) => {
+                    fn push_entries(entries: &[TocEntry], depth: usize, items: &mut Vec<Item>) {
+                        for entry in entries.iter() {
+                            items.push(Item {
+                                marker: format!("{:indent$}• ", "", indent = depth * 2),
+                                indent: depth * 2 + 2,
+                                words: words(vec![Span {
+                                    text: entry.text.clone(),
+                                    style: Style::default(),
+                                }]),
+                            });
+                            push_entries(&entry.children, depth + 1, items);
+                        }
+                    }
+
+                    let mut items = vec![];
+                    push_entries(&toc.entries, 0, &mut items);
+                    items
+                }
+                Block::LinkDefinition(_) | Block::Comment(..) | Block::Separator(..) => continue,
+            };
+            if items.iter().all(|item| item.words.is_empty()) {
+                continue;
+            }
+
+            if !first {
+                writeln!(output)?;
+            }
+            for item in items.iter() {
+                write_item(output, item, options)?;
+            }
+            first = false;
+        }
+
+        Ok(())
+    }
+}
+
+impl Renderer for AnsiOptions {
+    fn render<Writer: io::Write>(
+        &self,
+        md: &Markdown<'_>,
+        output: &mut Writer,
+        options: &RenderOptions,
+    ) -> io::Result<()> {
+        md.render_ansi(output, self, options)
+    }
+}
+
+/// Render `text` as styled spans, with emphasis markers and escapes dropped.  Soft breaks that
+/// `render_options` keeps are written as line breaks.
+fn spans(
+    text: &[MarkdownText<'_>],
+    definitions: &HashMap<String, &str>,
+    options: &AnsiOptions,
+    render_options: &RenderOptions,
+    style: &Style,
+) -> Vec<Span> {
+    /// Spans, and whether each one came straight from markdown and still has markup in it
+    fn push_spans(
+        text: &[MarkdownText<'_>],
+        definitions: &HashMap<String, &str>,
+        options: &AnsiOptions,
+        render_options: &RenderOptions,
+        style: &Style,
+        spans: &mut Vec<(Span, bool)>,
+    ) {
+        let span = |text: &str, style: &Style| Span {
+            text: text.to_string(),
+            style: style.clone(),
+        };
+        let link_style = |target: &str| Style {
+            underline: true,
+            link: Some(target.to_string()),
+            ..style.clone()
+        };
+
+        for (i, t) in text.iter().enumerate() {
+            match t {
+                MarkdownText::Text(s, _) => spans.push((span(s, style), true)),
+                MarkdownText::SoftBreak(_) => {
+                    spans.push((span(render_options.soft_break_at(text, i), style), false))
+                }
+                MarkdownText::Code { code, .. } => spans.push((
+                    span(
+                        code,
+                        &Style {
+                            code: true,
+                            ..style.clone()
+                        },
+                    ),
+                    false,
+                )),
+                MarkdownText::AutoLink(link) => {
+                    spans.push((span(&link.target, &link_style(&link.url())), false))
+                }
+                MarkdownText::Image(image) => match image_target(image, definitions) {
+                    Some(target) if options.color => {
+                        spans.push((span(&image.alt_text, &link_style(target)), true))
+                    }
+                    Some(target) => {
+                        spans.push((span(&image.alt_text, style), true));
+                        spans.push((span(&format!(" ({target})"), style), false));
+                    }
+                    None => spans.push((span(&image.alt_text, style), true)),
+                },
+                MarkdownText::Link(link) => match link_target(link, definitions) {
+                    Some(target) if options.color => {
+                        push_spans(
+                            &link.link_text,
+                            definitions,
+                            options,
+                            render_options,
+                            &link_style(target),
+                            spans,
+                        );
+                    }
+                    Some(target) => {
+                        push_spans(
+                            &link.link_text,
+                            definitions,
+                            options,
+                            render_options,
+                            style,
+                            spans,
+                        );
+                        if target != plain_text(&link.link_text).trim() {
+                            spans.push((span(&format!(" ({target})"), style), false));
+                        }
+                    }
+                    None => push_spans(
+                        &link.link_text,
+                        definitions,
+                        options,
+                        render_options,
+                        style,
+                        spans,
+                    ),
+                },
+            }
+        }
+    }
+
+    let mut raw = vec![];
+    push_spans(text, definitions, options, render_options, style, &mut raw);
+
+    let mut spans = Vec::with_capacity(raw.len());
+    for (i, (span, markup)) in raw.iter().enumerate() {
+        if !markup {
+            spans.push(span.clone());
+            continue;
+        }
+
+        let next = raw[i + 1..].iter().find_map(|(s, _)| s.text.chars().next());
+        let mut text = String::new();
+        strip_markup(&span.text, next, &mut text);
+        spans.push(Span {
+            text,
+            style: span.style.clone(),
+        });
+    }
+
+    spans
+}
+
+/// Split `spans` into words, each of which might be made of several differently styled spans.
+fn words(spans: Vec<Span>) -> Vec<Vec<Span>> {
+    let mut words = vec![];
+    let mut word: Vec<Span> = vec![];
+    for span in spans.into_iter() {
+        let mut current = String::new();
+        for c in span.text.chars() {
+            if !c.is_whitespace() {
+                current.push(c);
+                continue;
+            }
+            if !current.is_empty() {
+                word.push(Span {
+                    text: std::mem::take(&mut current),
+                    style: span.style.clone(),
+                });
+            }
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+        }
+        if !current.is_empty() {
+            word.push(Span {
+                text: current,
+                style: span.style,
+            });
+        }
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
+
+/// Break paragraph text into items: every line that starts with a list bullet starts a new item,
+/// and any other line continues the one before it, after a line break.
+fn items(spans: Vec<Span>) -> Vec<Item> {
+    let mut lines: Vec<Vec<Span>> = vec![vec![]];
+    for span in spans.into_iter() {
+        let mut parts = span.text.split('\n');
+        if let Some(part) = parts.next() {
+            lines.last_mut().expect("there's always a line").push(Span {
+                text: part.to_string(),
+                style: span.style.clone(),
+            });
+        }
+        for part in parts {
+            lines.push(vec![Span {
+                text: part.to_string(),
+                style: span.style.clone(),
+            }]);
+        }
+    }
+
+    let mut items: Vec<Item> = vec![];
+    for mut line in lines.into_iter() {
+        line.retain(|span| !span.text.is_empty());
+        let text: String = line.iter().map(|s| s.text.as_str()).collect();
+        let content = text.trim_start();
+        let depth = text.len() - content.len();
+        let is_bullet = ["- ", "* ", "+ "].iter().any(|m| content.starts_with(m))
+            && line.first().is_some_and(|span| span.text.len() >= depth + 2);
+
+        if is_bullet {
+            // drop the indentation and the bullet, which always come from a single span
+            let first = &mut line[0];
+            first.text = first.text[depth + 2..].trim_start().to_string();
+            items.push(Item {
+                marker: format!("{:depth$}• ", ""),
+                indent: depth + 2,
+                words: words(line),
+            });
+        } else {
+            let line = words(line);
+            match items.last_mut() {
+                Some(item) => {
+                    if !item.words.is_empty() && !line.is_empty() {
+                        item.words.push(vec![]);
+                    }
+                    item.words.extend(line);
+                }
+                None => items.push(Item {
+                    marker: String::new(),
+                    indent: 0,
+                    words: line,
+                }),
+            }
+        }
+    }
+
+    items
+}
+
+/// Write `item`, wrapping it to the width in `options`.
+fn write_item<Writer: io::Write>(
+    output: &mut Writer,
+    item: &Item,
+    options: &AnsiOptions,
+) -> io::Result<()> {
+    let word_width = |word: &[Span]| word.iter().map(|s| display_width(&s.text)).sum::<usize>();
+
+    write!(output, "{}", item.marker)?;
+    let mut column = item.indent;
+    let mut line_start = true;
+    for word in item.words.iter() {
+        if word.is_empty() {
+            write!(output, "\n{:indent$}", "", indent = item.indent)?;
+            column = item.indent;
+            line_start = true;
+            continue;
+        }
+
+        let width = word_width(word);
+        if !line_start {
+            if options.width.is_some_and(|max| column + 1 + width > max) {
+                write!(output, "\n{:indent$}", "", indent = item.indent)?;
+                column = item.indent;
+            } else {
+                write!(output, " ")?;
+                column += 1;
+            }
+        }
+
+        for span in word.iter() {
+            write_span(output, span, options.color)?;
+        }
+        column += width;
+        line_start = false;
+    }
+
+    writeln!(output)
+}
+
+/// Write the lines of an indented code block as they are, indented by four spaces.
+fn write_code<Writer: io::Write>(
+    output: &mut Writer,
+    lines: &[String],
+    options: &AnsiOptions,
+) -> io::Result<()> {
+    for line in lines.iter() {
+        if !line.is_empty() {
+            let span = Span {
+                text: line.clone(),
+                style: Style {
+                    code: true,
+                    ..Default::default()
+                },
+            };
+            write!(output, "    ")?;
+            write_span(output, &span, options.color)?;
+        }
+        writeln!(output)?;
+    }
+
+    Ok(())
+}
+
+/// `text` without any control characters but tabs, which could otherwise be used to send the
+/// terminal escape codes.
+fn strip_controls(text: &str) -> Cow<'_, str> {
+    let is_control = |c: char| c.is_control() && c != '\t';
+    match text.contains(is_control) {
+        true => Cow::Owned(text.chars().filter(|&c| !is_control(c)).collect()),
+        false => Cow::Borrowed(text),
+    }
+}
+
+/// Write `span` with the escape codes for its style.
+fn write_span<Writer: io::Write>(output: &mut Writer, span: &Span, color: bool) -> io::Result<()> {
+    let style = &span.style;
+    let text = strip_controls(&span.text);
+    if !color || *style == Style::default() {
+        return write!(output, "{text}");
+    }
+
+    let mut codes = vec![];
+    if style.bold {
+        codes.push("1");
+    }
+    if style.underline {
+        codes.push("4");
+    }
+    if style.code {
+        codes.push("36");
+    } else if style.link.is_some() {
+        codes.push("34");
+    }
+
+    if let Some(target) = &style.link {
+        write!(output, "\x1b]8;;{}\x1b\\", strip_controls(target))?;
+    }
+    write!(output, "\x1b[{}m{text}\x1b[0m", codes.join(";"))?;
+    if style.link.is_some() {
+        write!(output, "\x1b]8;;\x1b\\")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ansi(input: &str, options: &AnsiOptions) -> String {
+        let md = Markdown::parse(input);
+        let mut output = vec![];
+        md.write_ansi(&mut output, options).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn styles() {
+        assert_eq!(
+            ansi(
+                "# Title\n\nRun `make` or see [docs](https://x.y).",
+                &AnsiOptions::default()
+            ),
+            "\x1b[1;4mTitle\x1b[0m\n\n\
+             Run \x1b[36mmake\x1b[0m or see \
+             \x1b]8;;https://x.y\x1b\\\x1b[4;34mdocs\x1b[0m\x1b]8;;\x1b\\.\n"
+        );
+    }
+
+    #[test]
+    fn no_color() {
+        assert_eq!(
+            ansi(
+                "# Title\n\nRun `make` or see [docs](https://x.y).",
+                &AnsiOptions {
+                    width: None,
+                    color: false
+                }
+            ),
+            "Title\n\nRun make or see docs (https://x.y).\n"
+        );
+    }
+
+    #[test]
+    fn wrapping() {
+        assert_eq!(
+            ansi(
+                "Some words that\nneed wrapping here.\n\n\
+                 - a list item that is long\n  * nested one",
+                &AnsiOptions {
+                    width: Some(16),
+                    color: false
+                }
+            ),
+            "Some words that\nneed wrapping\nhere.\n\n\
+             • a list item\n  that is long\n  • nested one\n"
+        );
+    }
+
+    #[test]
+    fn indented_code() {
+        assert_eq!(
+            ansi(
+                "Some code:\n\n    fn main() {\n        println!(\"hi\");\n    }\n",
+                &AnsiOptions {
+                    width: Some(16),
+                    color: false
+                }
+            ),
+            "Some code:\n\n    fn main() {\n        println!(\"hi\");\n    }\n"
+        );
+        assert_eq!(
+            ansi("    a  b", &AnsiOptions::default()),
+            "    \x1b[36ma  b\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn control_characters() {
+        assert_eq!(
+            ansi(
+                "[docs](https://x.y/\x1b]0;pwned\x07) \x1b[2J`\u{9b}0m`",
+                &AnsiOptions::default()
+            ),
+            "\x1b]8;;https://x.y/]0;pwned\x1b\\\x1b[4;34mdocs\x1b[0m\x1b]8;;\x1b\\ \
+             [2J\x1b[36m0m\x1b[0m\n"
+        );
+        assert_eq!(
+            ansi(
+                "[docs](https://x.y/\x1b]0;pwned\x07)",
+                &AnsiOptions {
+                    width: None,
+                    color: false
+                }
+            ),
+            "docs (https://x.y/]0;pwned)\n"
+        );
+    }
+}
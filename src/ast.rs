@@ -0,0 +1,684 @@
+//! A serializable form of a [`Markdown`] document, for working with documents outside of Rust.
+//!
+//! The parsed document borrows from its source, so this module has an owned copy of the tree
+//! that can be written out as JSON (or anything else serde supports) and read back in.  Each node
+//! is tagged with its `type` and, where it's known, the byte range of the source it came from.
+//!
+//! Documents can also be built in code, starting from [`Document::new`].
+
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    escape_markdown,
+    parser::{
+        self, front_matter::parse_front_matter, headers::Header, images::ImageRef, links::LinkRef,
+        util::MarkdownText,
+    },
+    toc::Toc,
+    AsHtml, AsText, Markdown,
+};
+
+pub use crate::{parser::headers::HeadingLevel, span::Span};
+
+/// A whole document.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Document {
+    /// The front matter, exactly as it was written, delimiters included
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub front_matter: Option<String>,
+    pub blocks: Vec<Block>,
+}
+
+/// How a heading was written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase", tag = "style")]
+pub enum HeadingSyntax {
+    /// `## Heading`
+    Atx,
+    /// A heading underlined with `=` or `-`
+    Setext { underline_length: usize },
+}
+
+/// Where a link or image points to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Destination {
+    /// A url, written inline
+    Inline(String),
+    /// The label of a link definition
+    Reference(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Block {
+    Paragraph {
+        #[serde(default)]
+        span: Option<Span>,
+        children: Vec<Inline>,
+    },
+    Heading {
+        #[serde(default)]
+        span: Option<Span>,
+        level: HeadingLevel,
+        #[serde(flatten)]
+        syntax: HeadingSyntax,
+        children: Vec<Inline>,
+    },
+    LinkDefinition {
+        #[serde(default)]
+        span: Option<Span>,
+        label: String,
+        target: String,
+        #[serde(default)]
+        title: Option<String>,
+    },
+    Comment {
+        #[serde(default)]
+        span: Option<Span>,
+        /// The whole comment, including `<!--` and `-->`
+        html: String,
+    },
+    Toc {
+        toc: Toc,
+    },
+    /// Blank space between blocks
+    Separator {
+        #[serde(default)]
+        span: Option<Span>,
+        newlines: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Inline {
+    Text {
+        #[serde(default)]
+        span: Option<Span>,
+        text: String,
+    },
+    SoftBreak {
+        #[serde(default)]
+        span: Option<Span>,
+    },
+    Code {
+        #[serde(default)]
+        span: Option<Span>,
+        code: String,
+    },
+    Link {
+        #[serde(default)]
+        span: Option<Span>,
+        destination: Destination,
+        #[serde(default)]
+        title: Option<String>,
+        children: Vec<Inline>,
+    },
+    Image {
+        #[serde(default)]
+        span: Option<Span>,
+        alt: String,
+        destination: Destination,
+        #[serde(default)]
+        title: Option<String>,
+    },
+    AutoLink {
+        #[serde(default)]
+        span: Option<Span>,
+        target: String,
+    },
+}
+
+impl Block {
+    /// A paragraph, with the start of each line escaped so it isn't read as some other block.
+    pub(crate) fn paragraph(mut children: Vec<Inline>) -> Self {
+        let mut line_start = true;
+        for child in children.iter_mut() {
+            match child {
+                Inline::SoftBreak { .. } => line_start = true,
+                Inline::Text { text, .. } if text.is_empty() => {}
+                Inline::Text { text, .. } if line_start => {
+                    escape_line_start(text);
+                    line_start = false;
+                }
+                _ => line_start = false,
+            }
+        }
+
+        Block::Paragraph {
+            span: None,
+            children,
+        }
+    }
+}
+
+impl Inline {
+    /// Text that's written out exactly as it's given, rather than read as markdown.
+    pub(crate) fn escaped_text(text: &str) -> Self {
+        // a line break could start a new block, so text stays on one line
+        let text = text.replace(['\r', '\n'], " ");
+        Inline::Text {
+            span: None,
+            text: escape_markdown(&text).into_owned(),
+        }
+    }
+
+    /// Inline code.  Code spans can't have backticks in them, so code that does is escaped text
+    /// instead.
+    pub(crate) fn code(code: &str) -> Self {
+        if code.is_empty() || code.contains('`') {
+            return Inline::escaped_text(code);
+        }
+
+        Inline::Code {
+            span: None,
+            code: code.to_string(),
+        }
+    }
+}
+
+/// Escape the marker at the start of `line` if it would start a heading, quote, list item or
+/// setext underline.
+fn escape_line_start(line: &mut String) {
+    let start = line.len() - line.trim_start_matches(' ').len();
+    let rest = &line[start..];
+    if rest.starts_with(['#', '>', '-', '+', '=']) {
+        line.insert(start, '\\');
+        return;
+    }
+
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let after = rest[digits..].chars().nth(1);
+    if digits > 0 && rest[digits..].starts_with(['.', ')']) && after.is_none_or(char::is_whitespace)
+    {
+        line.insert(start + digits, '\\');
+    }
+}
+
+impl Document {
+    /// An empty document, to add blocks to.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a heading with `text` in it.
+    pub fn heading(self, level: HeadingLevel, text: &str) -> Self {
+        self.heading_with(level, |h| h.text(text))
+    }
+
+    /// Add a heading with the content that `build` adds to it.
+    pub fn heading_with(
+        mut self,
+        level: HeadingLevel,
+        build: impl FnOnce(Inlines) -> Inlines,
+    ) -> Self {
+        self.push(Block::Heading {
+            span: None,
+            level,
+            syntax: HeadingSyntax::Atx,
+            children: build(Inlines::default()).children,
+        });
+        self
+    }
+
+    /// Add a paragraph with the content that `build` adds to it.
+    pub fn paragraph(mut self, build: impl FnOnce(Inlines) -> Inlines) -> Self {
+        self.push(Block::paragraph(build(Inlines::default()).children));
+        self
+    }
+
+    /// Add `block` after a blank line, and end the document with a newline.
+    fn push(&mut self, block: Block) {
+        match self.blocks.last_mut() {
+            Some(Block::Separator { newlines, .. }) => *newlines = 2,
+            Some(_) => self.blocks.push(Block::Separator {
+                span: None,
+                newlines: 2,
+            }),
+            None => {}
+        }
+        self.blocks.push(block);
+        self.blocks.push(Block::Separator {
+            span: None,
+            newlines: 1,
+        });
+    }
+}
+
+/// The content of a heading or paragraph that's being built.  Text is escaped, so it's written
+/// out exactly as it was given rather than read as markdown.
+#[derive(Debug, Default, Clone)]
+pub struct Inlines {
+    children: Vec<Inline>,
+}
+
+impl Inlines {
+    pub fn text(mut self, text: &str) -> Self {
+        self.children.push(Inline::escaped_text(text));
+        self
+    }
+
+    /// Add inline code.  Code spans can't have backticks in them, so code that does is added as
+    /// text instead.
+    pub fn code(mut self, code: &str) -> Self {
+        self.children.push(Inline::code(code));
+        self
+    }
+
+    pub fn link(self, text: &str, url: &str) -> Self {
+        self.link_with(url, |l| l.text(text))
+    }
+
+    /// Add a link to `url`, with the text that `build` adds to it.
+    pub fn link_with(mut self, url: &str, build: impl FnOnce(Inlines) -> Inlines) -> Self {
+        self.children.push(Inline::Link {
+            span: None,
+            destination: Destination::Inline(escape_url(url)),
+            title: None,
+            children: build(Inlines::default()).children,
+        });
+        self
+    }
+
+    pub fn image(mut self, alt: &str, url: &str) -> Self {
+        self.children.push(Inline::Image {
+            span: None,
+            alt: escape_markdown(&alt.replace(['\r', '\n'], " ")).into_owned(),
+            destination: Destination::Inline(escape_url(url)),
+            title: None,
+        });
+        self
+    }
+
+    /// Add a url that links to itself, like `<https://example.com>`.
+    pub fn auto_link(mut self, url: &str) -> Self {
+        self.children.push(Inline::AutoLink {
+            span: None,
+            target: escape_url(url),
+        });
+        self
+    }
+
+    /// Continue on the next line.
+    pub fn soft_break(mut self) -> Self {
+        self.children.push(Inline::SoftBreak { span: None });
+        self
+    }
+}
+
+/// Percent-encode the characters that would end a url early in markdown, or in an html attribute.
+pub(crate) fn escape_url(url: &str) -> String {
+    let mut escaped = String::with_capacity(url.len());
+    for c in url.chars() {
+        if c.is_whitespace() || matches!(c, '"' | '(' | ')' | '<' | '>') {
+            for byte in c.encode_utf8(&mut [0; 4]).bytes() {
+                escaped.push_str(&format!("%{byte:02X}"));
+            }
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// The span of a node, unless it was never parsed and so doesn't have one.
+fn known(span: Span) -> Option<Span> {
+    (span != Span::default()).then_some(span)
+}
+
+impl<'source> Markdown<'source> {
+    fn ast_inlines(&self, text: &[MarkdownText<'_>]) -> Vec<Inline> {
+        text.iter()
+            .map(|t| match t {
+                MarkdownText::Text(text, span) => Inline::Text {
+                    span: known(*span),
+                    text: text.to_string(),
+                },
+                MarkdownText::SoftBreak(span) => Inline::SoftBreak { span: known(*span) },
+                MarkdownText::Code { code, span } => Inline::Code {
+                    span: known(*span),
+                    code: code.to_string(),
+                },
+                MarkdownText::Link(link) => Inline::Link {
+                    span: known(link.span),
+                    destination: match &link.link_ref {
+                        LinkRef::Inline(target) => Destination::Inline(target.to_string()),
+                        LinkRef::Ref(label) => Destination::Reference(label.to_string()),
+                    },
+                    title: link.title.as_deref().map(str::to_string),
+                    children: self.ast_inlines(&link.link_text),
+                },
+                MarkdownText::Image(image) => Inline::Image {
+                    span: known(image.span),
+                    alt: image.alt_text.to_string(),
+                    destination: match &image.image_ref {
+                        ImageRef::Inline(target) => Destination::Inline(target.to_string()),
+                        ImageRef::Ref(label) => Destination::Reference(label.to_string()),
+                    },
+                    title: image.title.as_deref().map(str::to_string),
+                },
+                MarkdownText::AutoLink(link) => Inline::AutoLink {
+                    span: known(link.span),
+                    target: link.url().into_owned(),
+                },
+            })
+            .collect()
+    }
+
+    /// Copy this document into its serializable form.
+    ///
+    /// Spans cover the source text each node was parsed from; nodes added by formatting have no
+    /// span.
+    pub fn to_ast(&self) -> Document {
+        let blocks = self
+            .blocks
+            .iter()
+            .map(|block| match block {
+                parser::block::Block::Paragraph(p) => Block::Paragraph {
+                    span: known(p.span),
+                    children: self.ast_inlines(&p.text),
+                },
+                parser::block::Block::Heading(h) => {
+                    let syntax = match h {
+                        Header::AtxHeader { .. } => HeadingSyntax::Atx,
+                        Header::SetextHeader { level_len, .. } => HeadingSyntax::Setext {
+                            underline_length: *level_len,
+                        },
+                    };
+                    Block::Heading {
+                        span: known(h.span()),
+                        level: h.level(),
+                        syntax,
+                        children: self.ast_inlines(h.text()),
+                    }
+                }
+                parser::block::Block::LinkDefinition(d) => Block::LinkDefinition {
+                    span: known(d.span),
+                    label: d.label.to_string(),
+                    target: d.target.to_string(),
+                    title: d.title.as_deref().map(str::to_string),
+                },
+                parser::block::Block::Comment(c, span) => Block::Comment {
+                    span: known(*span),
+                    html: c.to_string(),
+                },
+                parser::block::Block::Toc(toc) => Block::Toc { toc: toc.clone() },
+                parser::block::Block::Separator(newlines, span) => Block::Separator {
+                    span: known(*span),
+                    newlines: *newlines,
+                },
+            })
+            .collect();
+
+        Document {
+            front_matter: self.front_matter.as_ref().map(|f| f.raw.to_string()),
+            blocks,
+        }
+    }
+}
+
+fn markdown_text(inlines: &[Inline]) -> Vec<MarkdownText<'_>> {
+    inlines
+        .iter()
+        .map(|inline| match inline {
+            Inline::Text { text, span } => {
+                MarkdownText::Text(Cow::Borrowed(text), span.unwrap_or_default())
+            }
+            Inline::SoftBreak { span } => MarkdownText::SoftBreak(span.unwrap_or_default()),
+            Inline::Code { code, span } => MarkdownText::Code {
+                code: Cow::Borrowed(code),
+                span: span.unwrap_or_default(),
+            },
+            Inline::Link {
+                destination,
+                title,
+                children,
+                span,
+            } => MarkdownText::Link(parser::links::Link {
+                link_text: markdown_text(children),
+                link_ref: match destination {
+                    Destination::Inline(target) => LinkRef::Inline(Cow::Borrowed(target)),
+                    Destination::Reference(label) => LinkRef::Ref(Cow::Borrowed(label)),
+                },
+                title: title.as_deref().map(Cow::Borrowed),
+                span: span.unwrap_or_default(),
+            }),
+            Inline::Image {
+                alt,
+                destination,
+                title,
+                span,
+            } => MarkdownText::Image(parser::images::Image {
+                alt_text: Cow::Borrowed(alt),
+                image_ref: match destination {
+                    Destination::Inline(target) => ImageRef::Inline(Cow::Borrowed(target)),
+                    Destination::Reference(label) => ImageRef::Ref(Cow::Borrowed(label)),
+                },
+                title: title.as_deref().map(Cow::Borrowed),
+                span: span.unwrap_or_default(),
+            }),
+            Inline::AutoLink { target, span } => MarkdownText::AutoLink(parser::links::AutoLink {
+                target: Cow::Borrowed(target),
+                literal: false,
+                span: span.unwrap_or_default(),
+            }),
+        })
+        .collect()
+}
+
+impl<'a> From<&'a Document> for Markdown<'a> {
+    /// Build a document that can be written out again, borrowing its text from `document`.
+    fn from(document: &'a Document) -> Self {
+        let blocks = document
+            .blocks
+            .iter()
+            .map(|block| match block {
+                Block::Paragraph { children, span } => {
+                    parser::block::Block::Paragraph(parser::paragraphs::Paragraph {
+                        text: markdown_text(children),
+                        span: span.unwrap_or_default(),
+                    })
+                }
+                Block::Heading {
+                    level,
+                    syntax,
+                    children,
+                    span,
+                } => parser::block::Block::Heading(match syntax {
+                    HeadingSyntax::Atx => Header::AtxHeader {
+                        level: *level,
+                        text: markdown_text(children),
+                        span: span.unwrap_or_default(),
+                    },
+                    HeadingSyntax::Setext { underline_length } => Header::SetextHeader {
+                        level: *level,
+                        level_len: *underline_length,
+                        text: markdown_text(children),
+                        span: span.unwrap_or_default(),
+                    },
+                }),
+                Block::LinkDefinition {
+                    label,
+                    target,
+                    title,
+                    span,
+                } => parser::block::Block::LinkDefinition(parser::links::LinkDefinition {
+                    label: Cow::Borrowed(label),
+                    target: Cow::Borrowed(target),
+                    title: title.as_deref().map(Cow::Borrowed),
+                    span: span.unwrap_or_default(),
+                }),
+                Block::Comment { html, span } => {
+                    parser::block::Block::Comment(Cow::Borrowed(html), span.unwrap_or_default())
+                }
+                Block::Toc { toc } => parser::block::Block::Toc(toc.clone()),
+                Block::Separator { newlines, span } => {
+                    parser::block::Block::Separator(*newlines, span.unwrap_or_default())
+                }
+            })
+            .collect();
+
+        Markdown {
+            front_matter: document
+                .front_matter
+                .as_deref()
+                .and_then(|raw| parse_front_matter(raw).ok())
+                .map(|(_, front_matter)| front_matter),
+            blocks,
+            line_index: Default::default(),
+            warnings: vec![],
+        }
+    }
+}
+
+impl AsText for Document {
+    fn write_as_text<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+        Markdown::from(self).write_as_text(output)
+    }
+}
+
+impl AsHtml for Document {
+    fn write_html<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+        Markdown::from(self).write_html(output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{AsHtml, AsText};
+
+    const DOCUMENT: &str =
+        "---\ntitle: x\n---\n\n# Intro\n\nSee [docs](https://x.y \"Docs\") and `code`,\n\
+                            ![logo][l] or <https://a.b>.\n\n[l]: logo.png\n";
+
+    #[test]
+    fn round_trip() {
+        let md = Markdown::parse(DOCUMENT);
+        let json = serde_json::to_string(&md.to_ast()).unwrap();
+        let document: Document = serde_json::from_str(&json).unwrap();
+        assert_eq!(document, md.to_ast());
+
+        let mut output = vec![];
+        Markdown::from(&document)
+            .write_as_text(&mut output)
+            .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), DOCUMENT);
+    }
+
+    #[test]
+    fn json() {
+        let md = Markdown::parse("## Hi `x`");
+        let json = serde_json::to_value(md.to_ast()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "blocks": [{
+                    "type": "heading",
+                    "span": {"start": 0, "end": 9},
+                    "level": 2,
+                    "style": "atx",
+                    "children": [
+                        {"type": "text", "span": {"start": 3, "end": 6}, "text": "Hi "},
+                        {"type": "code", "span": {"start": 6, "end": 9}, "code": "x"},
+                    ],
+                }],
+            })
+        );
+    }
+
+    #[test]
+    fn break_spans() {
+        let md = Markdown::parse("a\nb\n\nc");
+        let json = serde_json::to_value(md.to_ast()).unwrap();
+        assert_eq!(
+            json["blocks"][0]["children"][1],
+            serde_json::json!({"type": "soft_break", "span": {"start": 1, "end": 2}})
+        );
+        assert_eq!(
+            json["blocks"][1],
+            serde_json::json!({"type": "separator", "span": {"start": 3, "end": 5}, "newlines": 2})
+        );
+    }
+
+    #[test]
+    fn from_json() {
+        let document: Document = serde_json::from_str(
+            r#"{"blocks": [{"type": "paragraph", "children": [
+                {"type": "text", "text": "Go "},
+                {"type": "link", "destination": {"inline": "https://x.y"},
+                 "children": [{"type": "text", "text": "here"}]}
+            ]}]}"#,
+        )
+        .unwrap();
+        let mut output = vec![];
+        Markdown::from(&document).write_html(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "<p>Go <a href=\"https://x.y\">here</a></p>"
+        );
+    }
+
+    #[test]
+    fn builder() {
+        let document = Document::new()
+            .heading(HeadingLevel::H2, "Changes in *1.2*")
+            .paragraph(|p| {
+                p.text("See ")
+                    .link("the [docs]", "https://x.y/a b")
+                    .text(" for ")
+                    .code("a_b")
+                    .text(" & <more>.")
+            })
+            .paragraph(|p| {
+                p.text("# not a heading")
+                    .soft_break()
+                    .image("Logo", "logo.png")
+            });
+
+        let mut output = vec![];
+        document.write_as_text(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "## Changes in \\*1.2\\*\n\n\
+             See [the \\[docs\\]](https://x.y/a%20b) for `a_b` & \\<more>.\n\n\
+             \\# not a heading\n![Logo](logo.png)\n"
+        );
+
+        let mut output = vec![];
+        document.write_html(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "<h2 id=\"changes-in-12\">Changes in *1.2*</h2>\n\
+             <p>See <a href=\"https://x.y/a%20b\">the [docs]</a> for <code>a_b</code> \
+             &amp; &lt;more>.</p>\n\
+             <p># not a heading\n<img src=\"logo.png\" alt=\"Logo\"/></p>\n"
+        );
+    }
+
+    #[test]
+    fn builder_escapes_line_starts() {
+        let document = Document::new().paragraph(|p| {
+            p.text("1. first")
+                .soft_break()
+                .text("- not a list")
+                .soft_break()
+                .text("2) nor this, but 3.5 is fine")
+                .soft_break()
+                .text("===")
+        });
+
+        let markdown = document.to_markdown();
+        assert_eq!(
+            markdown,
+            "1\\. first\n\\- not a list\n2\\) nor this, but 3.5 is fine\n\\===\n"
+        );
+        assert_eq!(
+            Markdown::parse(&markdown).to_html(),
+            "<p>1. first\n- not a list\n2) nor this, but 3.5 is fine\n===\n</p>"
+        );
+    }
+}
@@ -0,0 +1,382 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use color_eyre::eyre::{bail, eyre, Context, Result};
+use globset::{GlobBuilder, GlobSetBuilder};
+use md2md::{
+    format::{
+        DefinitionPlacement, EmphasisStyle, HeadingStyle, LinkStyle, ListMarker, ReferenceLabels,
+        Wrap,
+    },
+    parser::{headers::HeadingLevel, Dialect, Extension, ParseOptions},
+};
+use serde::{Deserialize, Serialize};
+
+/// The name of the file we look for when discovering project configuration.
+pub const CONFIG_FILE_NAME: &str = ".md2md.toml";
+
+/// The effective configuration used when formatting a file.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub dialect: Dialect,
+    pub tab_width: usize,
+    pub heading_style: HeadingStyle,
+    pub list_marker: ListMarker,
+    pub emphasis_style: EmphasisStyle,
+    pub wrap: Wrap,
+    pub abbreviations: Vec<String>,
+    pub link_style: LinkStyle,
+    pub reference_labels: ReferenceLabels,
+    pub reference_placement: DefinitionPlacement,
+    pub toc_min_level: HeadingLevel,
+    pub toc_max_level: HeadingLevel,
+    /// Syntax read on top of what the dialect reads
+    pub extensions: BTreeSet<Extension>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            dialect: Dialect::default(),
+            tab_width: 4,
+            heading_style: HeadingStyle::default(),
+            list_marker: ListMarker::default(),
+            emphasis_style: EmphasisStyle::default(),
+            wrap: Wrap::default(),
+            abbreviations: vec![],
+            link_style: LinkStyle::default(),
+            reference_labels: ReferenceLabels::default(),
+            reference_placement: DefinitionPlacement::default(),
+            toc_min_level: HeadingLevel::H1,
+            toc_max_level: HeadingLevel::H6,
+            extensions: BTreeSet::new(),
+        }
+    }
+}
+
+/// A set of settings where everything is optional, as written in a config file.
+#[derive(Deserialize, Default, Debug, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+struct Settings {
+    dialect: Option<Dialect>,
+    tab_width: Option<usize>,
+    heading_style: Option<HeadingStyle>,
+    list_marker: Option<ListMarker>,
+    emphasis_style: Option<EmphasisStyle>,
+    wrap: Option<Wrap>,
+    abbreviations: Option<Vec<String>>,
+    link_style: Option<LinkStyle>,
+    reference_labels: Option<ReferenceLabels>,
+    reference_placement: Option<DefinitionPlacement>,
+    toc_min_level: Option<HeadingLevel>,
+    toc_max_level: Option<HeadingLevel>,
+    extensions: Option<BTreeSet<Extension>>,
+}
+
+/// Settings that only apply to files matching one of `files`.
+#[derive(Debug, PartialEq, Eq)]
+struct Override {
+    files: Vec<String>,
+    settings: Settings,
+}
+
+/// The contents of a `.md2md.toml` file.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfigFile {
+    settings: Settings,
+    overrides: Vec<Override>,
+}
+
+impl Config {
+    fn apply(&mut self, settings: &Settings) {
+        if let Some(dialect) = settings.dialect {
+            self.dialect = dialect;
+        }
+        if let Some(tab_width) = settings.tab_width {
+            self.tab_width = tab_width;
+        }
+        if let Some(heading_style) = settings.heading_style {
+            self.heading_style = heading_style;
+        }
+        if let Some(list_marker) = settings.list_marker {
+            self.list_marker = list_marker;
+        }
+        if let Some(emphasis_style) = settings.emphasis_style {
+            self.emphasis_style = emphasis_style;
+        }
+        if let Some(wrap) = settings.wrap {
+            self.wrap = wrap;
+        }
+        if let Some(abbreviations) = &settings.abbreviations {
+            self.abbreviations = abbreviations.clone();
+        }
+        if let Some(link_style) = settings.link_style {
+            self.link_style = link_style;
+        }
+        if let Some(reference_labels) = settings.reference_labels {
+            self.reference_labels = reference_labels;
+        }
+        if let Some(reference_placement) = settings.reference_placement {
+            self.reference_placement = reference_placement;
+        }
+        if let Some(toc_min_level) = settings.toc_min_level {
+            self.toc_min_level = toc_min_level;
+        }
+        if let Some(toc_max_level) = settings.toc_max_level {
+            self.toc_max_level = toc_max_level;
+        }
+        if let Some(extensions) = &settings.extensions {
+            self.extensions = extensions.clone();
+        }
+    }
+
+    /// How to read the files this applies to: the dialect, plus any extensions.
+    pub fn parse_options(&self) -> ParseOptions {
+        let mut options = ParseOptions::from(self.dialect);
+        for &extension in self.extensions.iter() {
+            options.enable(extension);
+        }
+        options
+    }
+
+    /// Find and load the configuration that applies to `input`.
+    ///
+    /// If `explicit` is given, that file is used.  Otherwise, we walk up from the directory
+    /// containing `input` and use the first `.md2md.toml` we find.  If there isn't one, the
+    /// defaults are used.
+    pub fn resolve(input: &Path, explicit: Option<&Path>) -> Result<Self> {
+        let path = match explicit {
+            Some(path) => Some(path.to_path_buf()),
+            None => discover(input),
+        };
+        let Some(path) = path else {
+            return Ok(Config::default());
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| eyre!("Error reading config file `{:?}`", &path))?;
+        let file = contents
+            .parse::<ConfigFile>()
+            .with_context(|| eyre!("Error parsing config file `{:?}`", &path))?;
+
+        let root = path.parent().unwrap_or_else(|| Path::new(""));
+        file.config_for(&relative_to(input, root))
+    }
+}
+
+impl ConfigFile {
+    /// Compute the configuration for the file at `path`, relative to the config file.
+    ///
+    /// Overrides are applied in the order they're written, so later overrides win.  In their
+    /// globs, `*` doesn't match across a `/`; use `**` for that.
+    pub fn config_for(&self, path: &Path) -> Result<Config> {
+        let mut config = Config::default();
+        config.apply(&self.settings);
+
+        for o in self.overrides.iter() {
+            let mut builder = GlobSetBuilder::new();
+            for pattern in o.files.iter() {
+                builder.add(
+                    GlobBuilder::new(pattern)
+                        .literal_separator(true)
+                        .build()
+                        .with_context(|| eyre!("Invalid glob `{pattern}` in overrides"))?,
+                );
+            }
+            if builder.build()?.is_match(path) {
+                config.apply(&o.settings);
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+impl FromStr for ConfigFile {
+    type Err = color_eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut table: toml::Table = s.parse()?;
+
+        let overrides = match table.remove("overrides") {
+            Some(toml::Value::Array(overrides)) => overrides
+                .into_iter()
+                .map(|o| {
+                    let toml::Value::Table(mut o) = o else {
+                        bail!("each entry in `overrides` must be a table");
+                    };
+                    let files = match o.remove("files") {
+                        Some(files) => Vec::<String>::deserialize(files)
+                            .context("`files` must be a list of globs")?,
+                        None => bail!("each entry in `overrides` needs a `files` list"),
+                    };
+                    Ok(Override {
+                        files,
+                        settings: toml::Value::Table(o).try_into()?,
+                    })
+                })
+                .collect::<Result<_>>()?,
+            Some(_) => bail!("`overrides` must be an array of tables"),
+            None => vec![],
+        };
+
+        Ok(ConfigFile {
+            settings: toml::Value::Table(table).try_into()?,
+            overrides,
+        })
+    }
+}
+
+/// Walk up from the directory containing `input`, looking for a config file.
+fn discover(input: &Path) -> Option<PathBuf> {
+    let input = input.canonicalize().ok()?;
+    input
+        .ancestors()
+        .skip(1)
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Express `path` relative to `root` if possible, so overrides can be written relative to the
+/// config file.
+fn relative_to(path: &Path, root: &Path) -> PathBuf {
+    let (Ok(path), Ok(root)) = (path.canonicalize(), root.canonicalize()) else {
+        return path.to_path_buf();
+    };
+    path.strip_prefix(&root)
+        .map(Path::to_path_buf)
+        .unwrap_or(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_config_file() {
+        let file: ConfigFile = r#"
+            dialect = "gfm"
+            tab_width = 2
+            heading_style = "atx"
+            list_marker = "*"
+            emphasis_style = "_"
+            wrap = 80
+            abbreviations = ["Fn."]
+            link_style = "reference"
+            reference_labels = "number"
+            reference_placement = "section"
+            toc_min_level = 2
+            toc_max_level = 3
+            extensions = ["front-matter"]
+        "#
+        .parse()
+        .unwrap();
+        let config = file.config_for(Path::new("README.md")).unwrap();
+        assert_eq!(
+            config,
+            Config {
+                dialect: Dialect::Gfm,
+                tab_width: 2,
+                heading_style: HeadingStyle::Atx,
+                list_marker: ListMarker::Asterisk,
+                emphasis_style: EmphasisStyle::Underscore,
+                wrap: Wrap::Width(80),
+                abbreviations: vec!["Fn.".to_string()],
+                link_style: LinkStyle::Reference,
+                reference_labels: ReferenceLabels::Number,
+                reference_placement: DefinitionPlacement::Section,
+                toc_min_level: HeadingLevel::H2,
+                toc_max_level: HeadingLevel::H3,
+                extensions: BTreeSet::from([Extension::FrontMatter]),
+            }
+        );
+    }
+
+    #[test]
+    fn extensions_turn_on_parse_options() {
+        let file: ConfigFile =
+            "dialect = \"commonmark\"\nextensions = [\"headings-without-space\"]"
+                .parse()
+                .unwrap();
+        let options = file
+            .config_for(Path::new("README.md"))
+            .unwrap()
+            .parse_options();
+        assert!(options.headings_without_space);
+        assert!(!options.front_matter);
+    }
+
+    #[test]
+    fn feature_gated_extensions() {
+        let file = "extensions = [\"autolink-literals\"]".parse::<ConfigFile>();
+        #[cfg(feature = "gfm")]
+        assert!(
+            file.unwrap()
+                .config_for(Path::new("a.md"))
+                .unwrap()
+                .parse_options()
+                .autolink_literals
+        );
+        #[cfg(not(feature = "gfm"))]
+        assert!(file.is_err());
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        assert!("tabwidth = 2".parse::<ConfigFile>().is_err());
+        assert!("toc_max_level = 7".parse::<ConfigFile>().is_err());
+        assert!("list_marker = \"x\"".parse::<ConfigFile>().is_err());
+        assert!("extensions = [\"tables\"]".parse::<ConfigFile>().is_err());
+        assert!("[[overrides]]\nfiles = [\"*.md\"]\nwarp = 80"
+            .parse::<ConfigFile>()
+            .is_err());
+    }
+
+    #[test]
+    fn overrides_apply_in_order() {
+        let file: ConfigFile = r#"
+            wrap = 80
+
+            [[overrides]]
+            files = ["docs/**/*.md"]
+            wrap = "never"
+            tab_width = 8
+
+            [[overrides]]
+            files = ["docs/generated/*.md"]
+            wrap = "preserve"
+        "#
+        .parse()
+        .unwrap();
+
+        let readme = file.config_for(Path::new("README.md")).unwrap();
+        assert_eq!(readme.wrap, Wrap::Width(80));
+        assert_eq!(readme.tab_width, 4);
+
+        let guide = file.config_for(Path::new("docs/guide/intro.md")).unwrap();
+        assert_eq!(guide.wrap, Wrap::Never);
+        assert_eq!(guide.tab_width, 8);
+
+        let generated = file.config_for(Path::new("docs/generated/api.md")).unwrap();
+        assert_eq!(generated.wrap, Wrap::Preserve);
+        assert_eq!(generated.tab_width, 8);
+
+        let nested = file.config_for(Path::new("docs/generated/v1/api.md")).unwrap();
+        assert_eq!(nested.wrap, Wrap::Never);
+    }
+
+    #[test]
+    fn print_config() {
+        let config = Config {
+            wrap: Wrap::Width(72),
+            ..Default::default()
+        };
+        let printed = toml::to_string(&config).unwrap();
+        assert!(printed.contains("wrap = 72"));
+        assert!(printed.contains("heading_style = \"preserve\""));
+        assert!(printed.contains("list_marker = \"preserve\""));
+    }
+}
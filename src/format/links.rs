@@ -0,0 +1,428 @@
+//! Converting links and images between inline and reference style.
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+};
+
+use crate::{
+    parser::{
+        block::Block,
+        headers::Header,
+        images::{Image, ImageRef},
+        links::{normalize_label, Link, LinkDefinition, LinkRef},
+        util::MarkdownText,
+    },
+    slug::{plain_text, slugify},
+    span::Span,
+    visit::{walk_inlines_mut, VisitorMut},
+};
+
+use super::{render_text, DefinitionPlacement, ReferenceLabels};
+
+/// The target and title of a link or image.
+type Destination<'source> = (Cow<'source, str>, Option<Cow<'source, str>>);
+
+/// Turns reference-style links and images into inline ones, using `definitions`.
+struct Inliner<'a, 'source> {
+    /// The target and title of each definition, by its normalized label
+    definitions: &'a HashMap<String, Destination<'source>>,
+}
+
+impl<'a, 'source> VisitorMut<'source> for Inliner<'a, 'source> {
+    fn visit_link(&mut self, link: &mut Link<'source>) {
+        if let LinkRef::Ref(label) = &link.link_ref {
+            let key = if label.is_empty() {
+                normalize_label(&render_text(&link.link_text))
+            } else {
+                normalize_label(label)
+            };
+            if let Some((target, title)) = self.definitions.get(&key) {
+                link.link_ref = LinkRef::Inline(target.clone());
+                link.title = title.clone();
+            }
+        }
+        walk_inlines_mut(self, &mut link.link_text);
+    }
+
+    fn visit_image(&mut self, image: &mut Image<'source>) {
+        let ImageRef::Ref(label) = &image.image_ref else {
+            return;
+        };
+        let key = if label.is_empty() {
+            normalize_label(&image.alt_text)
+        } else {
+            normalize_label(label)
+        };
+        if let Some((target, title)) = self.definitions.get(&key) {
+            image.image_ref = ImageRef::Inline(target.clone());
+            image.title = title.clone();
+        }
+    }
+}
+
+/// The number of newlines at the end of `blocks` once they're written out.
+fn trailing_newlines(blocks: &[Block<'_>]) -> usize {
+    let mut newlines = 0;
+    for block in blocks.iter().rev() {
+        match block {
+            Block::Separator(amount, _) => newlines += amount,
+            Block::Paragraph(p) if matches!(p.text.last(), Some(MarkdownText::SoftBreak(_))) => {
+                return newlines + 1
+            }
+            _ => break,
+        }
+    }
+
+    newlines
+}
+
+/// Replace every reference-style link and image that has a matching definition with an inline
+/// one, then remove the definitions that are no longer used.
+///
+/// We don't parse shortcut references (`[label]` on its own), so a definition is kept if its label
+/// still shows up in brackets anywhere in the document.
+pub(super) fn to_inline(blocks: &mut Vec<Block<'_>>) {
+    let mut definitions = HashMap::new();
+    for block in blocks.iter() {
+        if let Block::LinkDefinition(d) = block {
+            // the first definition of a label wins
+            definitions
+                .entry(normalize_label(&d.label))
+                .or_insert_with(|| (d.target.clone(), d.title.clone()));
+        }
+    }
+
+    let mut inliner = Inliner {
+        definitions: &definitions,
+    };
+    for block in blocks.iter_mut() {
+        inliner.visit_block(block);
+    }
+
+    let mut plain_text = String::new();
+    for block in blocks.iter() {
+        let text = match block {
+            Block::Paragraph(p) => &p.text,
+            Block::Heading(Header::AtxHeader { text, .. } | Header::SetextHeader { text, .. }) => {
+                text
+            }
+            Block::LinkDefinition(_) | Block::Comment(..) | Block::Toc(_) | Block::Separator(..) => {
+                continue
+            }
+        };
+        for t in text.iter() {
+            match t {
+                MarkdownText::Text(s, _) => plain_text.push_str(s),
+                MarkdownText::SoftBreak(_) => plain_text.push(' '),
+                _ => plain_text.push('\n'),
+            }
+        }
+        plain_text.push('\n');
+    }
+    let plain_text = plain_text
+        .lines()
+        .map(normalize_label)
+        .collect::<Vec<_>>()
+        .join("\n");
+    let is_used =
+        |d: &LinkDefinition<'_>| plain_text.contains(&format!("[{}]", normalize_label(&d.label)));
+
+    // drop unused definitions, along with the line break that ended them
+    let mut kept = Vec::with_capacity(blocks.len());
+    let mut skip_separator = false;
+    let mut removed_trailing = false;
+    for block in blocks.drain(..) {
+        match &block {
+            Block::LinkDefinition(d) if !is_used(d) => {
+                skip_separator = true;
+                removed_trailing = true;
+                continue;
+            }
+            Block::Separator(..) if skip_separator => {
+                skip_separator = false;
+                continue;
+            }
+            Block::Separator(..) => {}
+            _ => removed_trailing = false,
+        }
+        skip_separator = false;
+        kept.push(block);
+    }
+    if removed_trailing {
+        if let Some(Block::Separator(amount, _)) = kept.last_mut() {
+            *amount = 1;
+        }
+    }
+
+    *blocks = kept;
+}
+
+/// Generates labels for new link definitions, making sure they don't clash with any existing
+/// ones.
+struct Labeler<'source> {
+    style: ReferenceLabels,
+    taken: HashSet<String>,
+    by_target: HashMap<Destination<'source>, Cow<'source, str>>,
+    next_number: usize,
+    pending: Vec<LinkDefinition<'source>>,
+}
+
+impl<'source> Labeler<'source> {
+    fn label_for(
+        &mut self,
+        text: &str,
+        target: Cow<'source, str>,
+        title: Option<Cow<'source, str>>,
+    ) -> Cow<'source, str> {
+        let key = (target, title);
+        if let Some(label) = self.by_target.get(&key) {
+            return label.clone();
+        }
+
+        let label = match self.style {
+            ReferenceLabels::Slug => {
+                let base = match slugify(text) {
+                    base if base.is_empty() => "link".to_string(),
+                    base => base,
+                };
+                let mut label = base.clone();
+                let mut n = 1;
+                while self.taken.contains(&normalize_label(&label)) {
+                    label = format!("{base}-{n}");
+                    n += 1;
+                }
+                label
+            }
+            ReferenceLabels::Number => loop {
+                let label = self.next_number.to_string();
+                self.next_number += 1;
+                if !self.taken.contains(&label) {
+                    break label;
+                }
+            },
+        };
+
+        self.taken.insert(normalize_label(&label));
+        let label: Cow<'source, str> = label.into();
+        let (target, title) = key;
+        self.by_target
+            .insert((target.clone(), title.clone()), label.clone());
+        self.pending.push(LinkDefinition {
+            label: label.clone(),
+            target,
+            title,
+            span: Span::default(),
+        });
+
+        label
+    }
+
+    /// Add the definitions generated so far to the end of `blocks`, separated from what came
+    /// before by a blank line.
+    fn flush(&mut self, blocks: &mut Vec<Block<'source>>, followed_by_content: bool) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let newlines = trailing_newlines(blocks);
+        if !blocks.is_empty() && newlines < 2 {
+            blocks.push(Block::Separator(2 - newlines, Span::default()));
+        }
+        for d in self.pending.drain(..) {
+            blocks.push(Block::LinkDefinition(d));
+            blocks.push(Block::Separator(1, Span::default()));
+        }
+        if followed_by_content {
+            blocks.push(Block::Separator(1, Span::default()));
+        }
+    }
+}
+
+impl<'source> VisitorMut<'source> for Labeler<'source> {
+    fn visit_link(&mut self, link: &mut Link<'source>) {
+        if let LinkRef::Inline(target) = &link.link_ref {
+            let target = target.clone();
+            let label = self.label_for(&plain_text(&link.link_text), target, link.title.take());
+            link.link_ref = LinkRef::Ref(label);
+        }
+        walk_inlines_mut(self, &mut link.link_text);
+    }
+
+    fn visit_image(&mut self, image: &mut Image<'source>) {
+        if let ImageRef::Inline(target) = &image.image_ref {
+            let target = target.clone();
+            let label = self.label_for(&image.alt_text, target, image.title.take());
+            image.image_ref = ImageRef::Ref(label);
+        }
+    }
+}
+
+/// Replace every inline link and image with a reference-style one, adding a definition for each
+/// distinct target.
+pub(super) fn to_reference(
+    blocks: &mut Vec<Block<'_>>,
+    style: ReferenceLabels,
+    placement: DefinitionPlacement,
+) {
+    let mut labeler = Labeler {
+        style,
+        taken: HashSet::new(),
+        by_target: HashMap::new(),
+        next_number: 1,
+        pending: vec![],
+    };
+    for block in blocks.iter() {
+        if let Block::LinkDefinition(d) = block {
+            labeler.taken.insert(normalize_label(&d.label));
+            labeler
+                .by_target
+                .entry((d.target.clone(), d.title.clone()))
+                .or_insert_with(|| d.label.clone());
+        }
+    }
+
+    for mut block in std::mem::take(blocks) {
+        if placement == DefinitionPlacement::Section && matches!(block, Block::Heading(_)) {
+            labeler.flush(blocks, true);
+        }
+
+        labeler.visit_block(&mut block);
+        blocks.push(block);
+    }
+    labeler.flush(blocks, false);
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        format::{FormatOptions, LinkStyle},
+        AsText, Markdown,
+    };
+
+    use super::*;
+
+    fn convert(input: &str, options: FormatOptions) -> String {
+        let mut md = Markdown::parse(input);
+        md.format(&options);
+        let mut output = vec![];
+        md.write_as_text(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    fn reference(input: &str) -> String {
+        convert(
+            input,
+            FormatOptions {
+                link_style: LinkStyle::Reference,
+                ..Default::default()
+            },
+        )
+    }
+
+    fn inline(input: &str) -> String {
+        convert(
+            input,
+            FormatOptions {
+                link_style: LinkStyle::Inline,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("  the `docs` "), "the-docs");
+        assert_eq!(slugify("!!"), "");
+    }
+
+    #[test]
+    fn labels_match_heading_anchors() {
+        let mut md = Markdown::parse("# Fish & Chips\n\nSee [Fish & `Chips`](#fish--chips).\n");
+        md.format(&FormatOptions {
+            link_style: LinkStyle::Reference,
+            ..Default::default()
+        });
+        let anchor = md.heading_anchors().remove(0).id;
+        assert_eq!(anchor, "fish--chips");
+        assert!(md
+            .to_markdown()
+            .ends_with(&format!("[Fish & `Chips`][{anchor}].\n\n[{anchor}]: #{anchor}\n")));
+    }
+
+    #[test]
+    fn inline_to_reference() {
+        assert_eq!(
+            reference("See [the docs](https://docs.rs \"Docs\") and ![a logo](logo.png)."),
+            "See [the docs][the-docs] and ![a logo][a-logo].\n\n\
+             [the-docs]: https://docs.rs \"Docs\"\n\
+             [a-logo]: logo.png\n"
+        );
+    }
+
+    #[test]
+    fn reference_labels_are_deduplicated() {
+        assert_eq!(
+            reference("[docs](/a), [docs](/b), [again](/a)\n\n[docs-1]: /c\n"),
+            "[docs][docs], [docs][docs-2], [again][docs]\n\n\
+             [docs-1]: /c\n\n\
+             [docs]: /a\n\
+             [docs-2]: /b\n"
+        );
+    }
+
+    #[test]
+    fn numbered_reference_labels() {
+        let options = FormatOptions {
+            link_style: LinkStyle::Reference,
+            reference_labels: ReferenceLabels::Number,
+            ..Default::default()
+        };
+        assert_eq!(
+            convert("[a](/a) [b](/b) [c](/a)", options),
+            "[a][1] [b][2] [c][1]\n\n[1]: /a\n[2]: /b\n"
+        );
+    }
+
+    #[test]
+    fn definitions_at_end_of_section() {
+        let options = FormatOptions {
+            link_style: LinkStyle::Reference,
+            reference_placement: DefinitionPlacement::Section,
+            ..Default::default()
+        };
+        assert_eq!(
+            convert("# One\n\n[a](/a)\n\n# Two\n\n[b](/b)\n", options),
+            "# One\n\n[a][a]\n\n[a]: /a\n\n# Two\n\n[b][b]\n\n[b]: /b\n"
+        );
+    }
+
+    #[test]
+    fn reference_to_inline() {
+        assert_eq!(
+            inline("See [the docs][docs] and ![logo][].\n\n[docs]: https://docs.rs \"Docs\"\n[logo]: logo.png\n"),
+            "See [the docs](https://docs.rs \"Docs\") and ![logo](logo.png).\n"
+        );
+    }
+
+    #[test]
+    fn unused_definitions_are_dropped() {
+        assert_eq!(
+            inline("[a][A] and [b][missing]\n\n[a]: /a\n[unused]: /x\n"),
+            "[a](/a) and [b][missing]\n"
+        );
+        assert_eq!(
+            inline("[a][]\n\n[a]: /a\n\n# Heading"),
+            "[a](/a)\n\n# Heading"
+        );
+    }
+
+    #[test]
+    fn shortcut_references_keep_definitions() {
+        assert_eq!(
+            inline("a [shortcut] reference\n\n[Shortcut]: /x\n"),
+            "a [shortcut] reference\n\n[Shortcut]: /x\n"
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! Rewriting list bullets and emphasis markers to use the same character throughout.
+
+use std::borrow::Cow;
+
+use crate::parser::{paragraphs::Paragraph, util::MarkdownText};
+
+use super::{indented_code, render_text, EmphasisStyle, ListMarker};
+
+/// Give every bullet list item in `paragraph` the same bullet.  Thematic breaks like `* * *` and
+/// indented code are left alone.
+pub(super) fn restyle_list_markers(paragraph: &mut Paragraph<'_>, marker: ListMarker) {
+    let bullet = match marker {
+        ListMarker::Preserve => return,
+        ListMarker::Dash => '-',
+        ListMarker::Asterisk => '*',
+        ListMarker::Plus => '+',
+    };
+    if indented_code(&paragraph.text).is_some() {
+        return;
+    }
+
+    let mut line_start = true;
+    let mut iter = paragraph.text.iter_mut().peekable();
+    while let Some(t) = iter.next() {
+        let MarkdownText::Text(text, _) = t else {
+            line_start = matches!(t, MarkdownText::SoftBreak(_));
+            continue;
+        };
+        if !std::mem::take(&mut line_start) {
+            continue;
+        }
+
+        let indent = text.len() - text.trim_start_matches(' ').len();
+        let mut rest = text[indent..].chars();
+        let (Some(old @ ('-' | '*' | '+')), Some(' ')) = (rest.next(), rest.next()) else {
+            continue;
+        };
+        let whole_line = matches!(iter.peek(), None | Some(MarkdownText::SoftBreak(_)));
+        if old == bullet || whole_line && text.chars().all(|c| c == old || c == ' ') {
+            continue;
+        }
+
+        *text = Cow::Owned(format!(
+            "{}{bullet}{}",
+            &text[..indent],
+            &text[indent + 1..]
+        ));
+    }
+}
+
+/// A run of `*` or `_` that might open or close emphasis.
+struct Run {
+    /// Which of the text nodes it's in
+    node: usize,
+    /// The byte offset of the run within that node
+    start: usize,
+    len: usize,
+    marker: char,
+    can_open: bool,
+    can_close: bool,
+    /// Whether it's between two letters or digits, where `_` isn't read as emphasis
+    intraword: bool,
+}
+
+/// Write all of the emphasis in `text` with the marker `style` asks for.
+///
+/// Markers are only changed in pairs, and only when the new one would still be read as
+/// emphasis, so `a*b*c` stays as it is rather than becoming `a_b_c`.
+pub(super) fn restyle_emphasis(text: &mut [MarkdownText<'_>], style: EmphasisStyle) {
+    let marker = match style {
+        EmphasisStyle::Preserve => return,
+        EmphasisStyle::Asterisk => '*',
+        EmphasisStyle::Underscore => '_',
+    };
+
+    for t in text.iter_mut() {
+        if let MarkdownText::Link(link) = t {
+            restyle_emphasis(&mut link.link_text, style);
+        }
+    }
+
+    let runs = emphasis_runs(text);
+    let mut openers: Vec<&Run> = vec![];
+    let mut changes = vec![];
+    for run in runs.iter() {
+        if run.can_close {
+            let opener = openers
+                .iter()
+                .rposition(|o| o.marker == run.marker && o.len == run.len);
+            if let Some(i) = opener {
+                let opener = openers[i];
+                openers.truncate(i);
+                let allowed = marker == '*' || !(opener.intraword || run.intraword);
+                if run.marker != marker && run.len <= 3 && allowed {
+                    changes.extend([opener, run]);
+                }
+                continue;
+            }
+        }
+        if run.can_open {
+            openers.push(run);
+        }
+    }
+
+    for change in changes.into_iter() {
+        if let MarkdownText::Text(s, _) = &mut text[change.node] {
+            let range = change.start..change.start + change.len;
+            s.to_mut()
+                .replace_range(range, &marker.to_string().repeat(change.len));
+        }
+    }
+}
+
+/// Find the runs of `*` and `_` in the text nodes of `text`, and whether each could open or close
+/// emphasis, using the same rules as [`crate::plain::strip_markup`].
+fn emphasis_runs(text: &[MarkdownText<'_>]) -> Vec<Run> {
+    let rendered: Vec<String> = text
+        .iter()
+        .map(|t| render_text(std::slice::from_ref(t)))
+        .collect();
+
+    let mut runs = vec![];
+    for (node, t) in text.iter().enumerate() {
+        let MarkdownText::Text(s, _) = t else {
+            continue;
+        };
+        let before_node = rendered[..node]
+            .iter()
+            .rev()
+            .find_map(|r| r.chars().next_back());
+        let after_node = rendered[node + 1..].iter().find_map(|r| r.chars().next());
+
+        let chars: Vec<(usize, char)> = s.char_indices().collect();
+        let mut i = 0;
+        while i < chars.len() {
+            let (start, c) = chars[i];
+            if c == '\\' {
+                i += 2;
+                continue;
+            }
+            if !matches!(c, '*' | '_') {
+                i += 1;
+                continue;
+            }
+
+            let len = chars[i..].iter().take_while(|&&(_, d)| d == c).count();
+            let before = i.checked_sub(1).map(|j| chars[j].1).or(before_node);
+            let after = chars.get(i + len).map(|&(_, c)| c).or(after_node);
+            let is_space = |c: Option<char>| c.is_none_or(char::is_whitespace);
+            let is_punctuation = |c: Option<char>| c.is_some_and(|c| c.is_ascii_punctuation());
+
+            let left_flanking = !is_space(after) && (is_space(before) || is_punctuation(before));
+            let right_flanking = !is_space(before) && (is_space(after) || is_punctuation(after));
+            let intraword = !is_space(before)
+                && !is_space(after)
+                && !is_punctuation(before)
+                && !is_punctuation(after);
+            let (can_open, can_close) = match c {
+                '_' => (
+                    left_flanking && !right_flanking,
+                    right_flanking && !left_flanking,
+                ),
+                _ => (left_flanking || intraword, right_flanking || intraword),
+            };
+            runs.push(Run {
+                node,
+                start,
+                len,
+                marker: c,
+                can_open,
+                can_close,
+                intraword,
+            });
+            i += len;
+        }
+    }
+
+    runs
+}
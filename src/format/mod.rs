@@ -0,0 +1,773 @@
+//! Rewrites that normalize a parsed document before it gets written back out as markdown.
+
+use std::{borrow::Cow, fmt, str::FromStr};
+
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+mod links;
+mod markers;
+
+use crate::{
+    parser::{
+        block::Block,
+        headers::{Header, HeadingLevel},
+        paragraphs::Paragraph,
+        util::MarkdownText,
+    },
+    span::Span,
+    toc::TocOptions,
+    AsText, Markdown,
+};
+
+/// How headings should be written.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HeadingStyle {
+    /// Keep whatever style the heading was written in
+    #[default]
+    Preserve,
+    /// Write headings as `# Heading`
+    Atx,
+    /// Write H1 and H2 headings underlined with `=` or `-`.  Other levels can't be written this
+    /// way, so they're written as ATX headings.
+    Setext,
+}
+
+impl FromStr for HeadingStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(HeadingStyle::Preserve),
+            "atx" => Ok(HeadingStyle::Atx),
+            "setext" => Ok(HeadingStyle::Setext),
+            _ => Err(format!(
+                "expected one of `preserve`, `atx` or `setext`, found `{s}`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for HeadingStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeadingStyle::Preserve => write!(f, "preserve"),
+            HeadingStyle::Atx => write!(f, "atx"),
+            HeadingStyle::Setext => write!(f, "setext"),
+        }
+    }
+}
+
+/// How paragraphs should be wrapped.  When written out (in a config file, or on the command line),
+/// this is either a column count or one of `preserve`, `never` and `sentence`.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Wrap {
+    /// Keep existing line breaks
+    #[default]
+    Preserve,
+    /// Join each paragraph onto a single line
+    Never,
+    /// Reflow paragraphs to fit within the given number of columns
+    Width(usize),
+    /// Put each sentence on its own line, also known as semantic line breaks
+    Sentence,
+}
+
+impl FromStr for Wrap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(Wrap::Preserve),
+            "never" => Ok(Wrap::Never),
+            "sentence" => Ok(Wrap::Sentence),
+            _ => match s.parse() {
+                Ok(0) => Err("wrap width must be greater than 0".to_string()),
+                Ok(width) => Ok(Wrap::Width(width)),
+                Err(_) => Err(format!(
+                    "expected a column count, `preserve`, `never` or `sentence`, found `{s}`"
+                )),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Wrap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Wrap::Preserve => write!(f, "preserve"),
+            Wrap::Never => write!(f, "never"),
+            Wrap::Width(width) => write!(f, "{width}"),
+            Wrap::Sentence => write!(f, "sentence"),
+        }
+    }
+}
+
+impl Serialize for Wrap {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Wrap::Width(width) => serializer.serialize_u64(*width as u64),
+            _ => serializer.collect_str(self),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Wrap {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Width(usize),
+            Mode(String),
+        }
+
+        match Repr::deserialize(deserializer)? {
+            Repr::Width(width) => width.to_string().parse(),
+            Repr::Mode(mode) => mode.parse(),
+        }
+        .map_err(de::Error::custom)
+    }
+}
+
+/// Which character bullet list items should start with.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListMarker {
+    /// Keep whatever bullet each item was written with
+    #[default]
+    #[serde(rename = "preserve")]
+    Preserve,
+    #[serde(rename = "-")]
+    Dash,
+    #[serde(rename = "*")]
+    Asterisk,
+    #[serde(rename = "+")]
+    Plus,
+}
+
+/// Which character emphasis and strong emphasis should be written with.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmphasisStyle {
+    /// Keep whatever markers the emphasis was written with
+    #[default]
+    #[serde(rename = "preserve")]
+    Preserve,
+    /// `*emphasis*` and `**strong emphasis**`
+    #[serde(rename = "*")]
+    Asterisk,
+    /// `_emphasis_` and `__strong emphasis__`.  Emphasis inside of a word, like `a*b*c`, keeps
+    /// its asterisks, since underscores there wouldn't be read as emphasis.
+    #[serde(rename = "_")]
+    Underscore,
+}
+
+/// How links and images should refer to their targets.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LinkStyle {
+    /// Keep whatever style the link was written in
+    #[default]
+    Preserve,
+    /// Write the target next to the text: `[text](https://example.com)`.  Definitions that are
+    /// no longer used get removed.
+    Inline,
+    /// Write a label next to the text, `[text][label]`, and define the label elsewhere.
+    Reference,
+}
+
+impl FromStr for LinkStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "preserve" => Ok(LinkStyle::Preserve),
+            "inline" => Ok(LinkStyle::Inline),
+            "reference" => Ok(LinkStyle::Reference),
+            _ => Err(format!(
+                "expected one of `preserve`, `inline` or `reference`, found `{s}`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LinkStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LinkStyle::Preserve => write!(f, "preserve"),
+            LinkStyle::Inline => write!(f, "inline"),
+            LinkStyle::Reference => write!(f, "reference"),
+        }
+    }
+}
+
+/// How labels are picked when converting links to reference style.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReferenceLabels {
+    /// Derive the label from the link text, e.g. `[the docs][the-docs]`
+    #[default]
+    Slug,
+    /// Number the labels in the order they appear, e.g. `[the docs][1]`
+    Number,
+}
+
+/// Where new link definitions are gathered.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DefinitionPlacement {
+    /// At the end of the document
+    #[default]
+    Document,
+    /// At the end of the section the link appears in, right before the next heading
+    Section,
+}
+
+/// Options controlling how [`Markdown::format`] rewrites a document.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct FormatOptions {
+    pub heading_style: HeadingStyle,
+    pub list_marker: ListMarker,
+    pub emphasis_style: EmphasisStyle,
+    pub wrap: Wrap,
+    /// Words ending in a period that don't end a sentence, in addition to [`ABBREVIATIONS`], and
+    /// matched the same way.  Only used with [`Wrap::Sentence`].
+    pub abbreviations: Vec<String>,
+    pub link_style: LinkStyle,
+    /// How to label new link definitions.  Only used with [`LinkStyle::Reference`].
+    pub reference_labels: ReferenceLabels,
+    /// Where to put new link definitions.  Only used with [`LinkStyle::Reference`].
+    pub reference_placement: DefinitionPlacement,
+    /// Which headings go into the table of contents, if the document has a `<!-- toc -->` marker
+    pub toc: TocOptions,
+}
+
+/// Common abbreviations that shouldn't be treated as the end of a sentence.  Lowercase ones match
+/// in any case, but capitalised ones like `No.` only match as written, so the word "no" can still
+/// end a sentence.
+pub const ABBREVIATIONS: &[&str] = &[
+    "e.g.", "i.e.", "cf.", "vs.", "viz.", "al.", "approx.", "Dr.", "Mr.", "Mrs.", "Ms.", "Prof.",
+    "Sr.", "Jr.", "St.", "Mt.", "No.", "Fig.", "Eq.", "Ch.", "Sec.", "Vol.", "pp.", "Inc.", "Ltd.",
+    "Co.", "Jan.", "Feb.", "Mar.", "Apr.", "Jun.", "Jul.", "Aug.", "Sep.", "Sept.", "Oct.", "Nov.",
+    "Dec.",
+];
+
+impl<'source> Markdown<'source> {
+    /// Normalize this document according to `options`.
+    pub fn format(&mut self, options: &FormatOptions) {
+        match options.link_style {
+            LinkStyle::Preserve => {}
+            LinkStyle::Inline => links::to_inline(&mut self.blocks),
+            LinkStyle::Reference => links::to_reference(
+                &mut self.blocks,
+                options.reference_labels,
+                options.reference_placement,
+            ),
+        }
+
+        for block in self.blocks.iter_mut() {
+            match block {
+                Block::Heading(header) => {
+                    restyle_header(header, options.heading_style);
+                    let (Header::AtxHeader { text, .. } | Header::SetextHeader { text, .. }) =
+                        header;
+                    markers::restyle_emphasis(text, options.emphasis_style);
+                }
+                Block::Paragraph(paragraph) => {
+                    markers::restyle_list_markers(paragraph, options.list_marker);
+                    if indented_code(&paragraph.text).is_none() {
+                        markers::restyle_emphasis(&mut paragraph.text, options.emphasis_style);
+                    }
+                    reflow(paragraph, options);
+                }
+                Block::LinkDefinition(_)
+                | Block::Comment(..)
+                | Block::Toc(_)
+                | Block::Separator(..) => {}
+            }
+        }
+
+        self.update_toc(&options.toc);
+    }
+}
+
+fn restyle_header(header: &mut Header<'_>, style: HeadingStyle) {
+    if style == HeadingStyle::Preserve {
+        return;
+    }
+
+    let level = header.level();
+    let span = header.span();
+    let (Header::AtxHeader { text, .. } | Header::SetextHeader { text, .. }) = header;
+    let text = std::mem::take(text);
+
+    *header = if style == HeadingStyle::Setext && level <= HeadingLevel::H2 {
+        Header::SetextHeader {
+            level,
+            level_len: rendered_width(&text).max(1),
+            text,
+            span,
+        }
+    } else {
+        Header::AtxHeader { level, text, span }
+    };
+}
+
+/// A run of inline content with no whitespace in it, which must stay on a single line.
+struct Word<'source> {
+    text: Vec<MarkdownText<'source>>,
+    rendered: String,
+}
+
+impl<'source> Word<'source> {
+    fn new(text: Vec<MarkdownText<'source>>) -> Self {
+        let rendered = render_text(&text);
+        Word { text, rendered }
+    }
+
+    fn width(&self) -> usize {
+        display_width(&self.rendered)
+    }
+
+    /// Whether this word can start a line without turning the paragraph into some other block,
+    /// like a heading, block quote or list item.
+    fn can_start_line(&self) -> bool {
+        let Some(first) = self.rendered.chars().next() else {
+            return true;
+        };
+        if "#>=-+*".contains(first) {
+            return false;
+        }
+
+        let digits = self.rendered.trim_start_matches(|c: char| c.is_ascii_digit());
+        !(digits.len() < self.rendered.len() && (digits == "." || digits == ")"))
+    }
+
+    /// Whether this word looks like the end of a sentence.
+    fn ends_sentence(&self, abbreviations: &[String]) -> bool {
+        if !matches!(self.text.last(), Some(MarkdownText::Text(..))) {
+            return false;
+        }
+
+        let word = self.rendered.trim_end_matches(['"', '\'', ')', ']', '*', '_']);
+        if !word.ends_with(['.', '!', '?']) {
+            return false;
+        }
+        if !word.ends_with('.') {
+            return true;
+        }
+
+        let word = word.trim_start_matches(['"', '\'', '(', '[', '*', '_']);
+        let mut chars = word.chars();
+        let is_initial = matches!(
+            (chars.next(), chars.next(), chars.next()),
+            (Some(c), Some('.'), None) if c.is_uppercase()
+        );
+
+        !is_initial
+            && !ABBREVIATIONS
+                .iter()
+                .copied()
+                .chain(abbreviations.iter().map(String::as_str))
+                .any(|a| match a.chars().any(char::is_uppercase) {
+                    true => a == word,
+                    false => a.eq_ignore_ascii_case(word),
+                })
+    }
+}
+
+/// `part` of `text`, still borrowed from the source if `text` is.
+fn slice_of<'source>(text: &Cow<'source, str>, part: &str) -> Cow<'source, str> {
+    match text {
+        Cow::Borrowed(text) => {
+            let start = part.as_ptr() as usize - text.as_ptr() as usize;
+            Cow::Borrowed(&text[start..start + part.len()])
+        }
+        Cow::Owned(_) => Cow::Owned(part.to_string()),
+    }
+}
+
+/// Break a run of inline content into words, splitting at whitespace and soft breaks.  Code,
+/// links, autolinks and images are never split, even if they contain whitespace.
+///
+/// A hard break (two trailing spaces followed by a newline) has to be kept, so the words are
+/// grouped into the segments between hard breaks.
+fn split_words(text: Vec<MarkdownText<'_>>) -> Vec<Vec<Word<'_>>> {
+    fn flush<'source>(current: &mut Vec<MarkdownText<'source>>, words: &mut Vec<Word<'source>>) {
+        if !current.is_empty() {
+            words.push(Word::new(std::mem::take(current)));
+        }
+    }
+
+    let mut segments = vec![];
+    let mut words = vec![];
+    let mut current = vec![];
+
+    let mut iter = text.into_iter().peekable();
+    while let Some(t) = iter.next() {
+        match t {
+            MarkdownText::Text(s, span) => {
+                if s.starts_with(char::is_whitespace) {
+                    flush(&mut current, &mut words);
+                }
+                for (i, word) in s.split_whitespace().enumerate() {
+                    if i > 0 {
+                        flush(&mut current, &mut words);
+                    }
+                    current.push(MarkdownText::Text(slice_of(&s, word), span.slice(&s, word)));
+                }
+                if s.ends_with(char::is_whitespace) {
+                    flush(&mut current, &mut words);
+                }
+                if s.ends_with("  ") && matches!(iter.peek(), Some(MarkdownText::SoftBreak(_))) {
+                    iter.next();
+                    segments.push(std::mem::take(&mut words));
+                }
+            }
+            MarkdownText::SoftBreak(_) => flush(&mut current, &mut words),
+            other => current.push(other),
+        }
+    }
+    flush(&mut current, &mut words);
+    segments.push(words);
+
+    segments
+}
+
+/// Rewrap the text of a paragraph.
+///
+/// Lines are broken between words, either when the next word would go past the wrap width or,
+/// with semantic line breaks, at the end of each sentence.  A break is skipped if the next word
+/// would change the meaning of the line it starts.  Indented code is left as it is.
+fn reflow(paragraph: &mut Paragraph<'_>, options: &FormatOptions) {
+    let width = match options.wrap {
+        Wrap::Preserve => return,
+        Wrap::Never | Wrap::Sentence => usize::MAX,
+        Wrap::Width(width) => width,
+    };
+    if indented_code(&paragraph.text).is_some() {
+        return;
+    }
+
+    let text = std::mem::take(&mut paragraph.text);
+    let trailing_break = matches!(text.last(), Some(MarkdownText::SoftBreak(_)));
+
+    for (i, segment) in split_words(text).into_iter().enumerate() {
+        if i > 0 {
+            paragraph.text.push(MarkdownText::Text("  ".into(), Span::default()));
+            paragraph.text.push(MarkdownText::SoftBreak(Span::default()));
+        }
+
+        let mut line_width = 0;
+        let mut end_of_sentence = false;
+        for (j, word) in segment.into_iter().enumerate() {
+            let word_width = word.width();
+            if j > 0 {
+                let too_long = line_width + 1 + word_width > width;
+                if (too_long || end_of_sentence) && word.can_start_line() {
+                    paragraph.text.push(MarkdownText::SoftBreak(Span::default()));
+                    line_width = 0;
+                } else {
+                    paragraph.text.push(MarkdownText::Text(" ".into(), Span::default()));
+                    line_width += 1;
+                }
+            }
+            line_width += word_width;
+            end_of_sentence =
+                options.wrap == Wrap::Sentence && word.ends_sentence(&options.abbreviations);
+            paragraph.text.extend(word.text);
+        }
+    }
+
+    if trailing_break {
+        paragraph.text.push(MarkdownText::SoftBreak(Span::default()));
+    }
+}
+
+/// Write out `text` as markdown.
+pub(crate) fn render_text(text: &[MarkdownText<'_>]) -> String {
+    let mut rendered = String::new();
+    for t in text.iter() {
+        t.write_as_text_fmt(&mut rendered)
+            .expect("writing to a String never fails");
+    }
+
+    rendered
+}
+
+/// The lines of `text` without their indentation, if it's code: every line is either empty or
+/// indented by four spaces.
+pub(crate) fn indented_code(text: &[MarkdownText<'_>]) -> Option<Vec<String>> {
+    let source = render_text(text);
+    if !source
+        .lines()
+        .all(|line| line.is_empty() || line.starts_with("    "))
+    {
+        return None;
+    }
+
+    let lines = source.trim_end_matches('\n').lines();
+    Some(lines.map(|line| line.get(4..).unwrap_or_default().to_string()).collect())
+}
+
+/// The number of columns `text` takes up once written as markdown.
+fn rendered_width(text: &[MarkdownText<'_>]) -> usize {
+    display_width(&render_text(text))
+}
+
+/// The number of terminal columns `s` takes up.
+///
+/// Each grapheme cluster is measured on its own, so combining marks and emoji sequences count as
+/// the single character a reader sees.
+pub fn display_width(s: &str) -> usize {
+    s.graphemes(true).map(|g| g.width().min(2)).sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn format(input: &str, options: &FormatOptions) -> String {
+        let mut md = Markdown::parse(input);
+        md.format(options);
+        let mut output = vec![];
+        md.write_as_text(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn atx_to_setext() {
+        let options = FormatOptions {
+            heading_style: HeadingStyle::Setext,
+            ..Default::default()
+        };
+        assert_eq!(format("# Title\n", &options), "Title\n=====\n");
+        assert_eq!(format("## Sub\n", &options), "Sub\n---\n");
+        assert_eq!(format("### Deep\n", &options), "### Deep\n");
+    }
+
+    #[test]
+    fn setext_to_atx() {
+        let options = FormatOptions {
+            heading_style: HeadingStyle::Atx,
+            ..Default::default()
+        };
+        assert_eq!(format("Title\n==", &options), "# Title");
+        assert_eq!(format("Sub\n---------", &options), "## Sub");
+    }
+
+    #[test]
+    fn setext_underline_is_resized() {
+        let options = FormatOptions {
+            heading_style: HeadingStyle::Setext,
+            ..Default::default()
+        };
+        assert_eq!(format("Title\n==", &options), "Title\n=====");
+    }
+
+    #[test]
+    fn preserve_keeps_underline() {
+        assert_eq!(
+            format("Title\n==", &FormatOptions::default()),
+            "Title\n=="
+        );
+    }
+
+    #[test]
+    fn parse_wrap() {
+        assert_eq!("80".parse(), Ok(Wrap::Width(80)));
+        assert_eq!("never".parse(), Ok(Wrap::Never));
+        assert_eq!("preserve".parse(), Ok(Wrap::Preserve));
+        assert_eq!("sentence".parse(), Ok(Wrap::Sentence));
+        assert!("0".parse::<Wrap>().is_err());
+        assert!("sometimes".parse::<Wrap>().is_err());
+    }
+
+    #[test]
+    fn wide_characters() {
+        assert_eq!(display_width("héllo"), 5);
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(display_width("日本語"), 6);
+        assert_eq!(display_width("👩‍👩‍👧"), 2);
+    }
+
+    #[test]
+    fn leaves_paragraphs_alone() {
+        let mut md = Markdown::parse("just text");
+        md.format(&FormatOptions {
+            heading_style: HeadingStyle::Atx,
+            ..Default::default()
+        });
+        assert_eq!(
+            md.blocks,
+            [Block::Paragraph(Paragraph {
+                text: vec![MarkdownText::Text("just text".into(), Span::new(0, 9))],
+                span: Span::new(0, 9),
+            })]
+        );
+    }
+
+    #[test]
+    fn list_markers() {
+        let options = FormatOptions {
+            list_marker: ListMarker::Dash,
+            ..Default::default()
+        };
+        assert_eq!(
+            format("* one\n  + two *2*\n\n* * *\n\n    * code\n", &options),
+            "- one\n  - two *2*\n\n* * *\n\n    * code\n"
+        );
+    }
+
+    #[test]
+    fn emphasis_style() {
+        let options = FormatOptions {
+            emphasis_style: EmphasisStyle::Underscore,
+            ..Default::default()
+        };
+        assert_eq!(
+            format("# *Title*\n\n*a* **b** [*c*](x) a*b*c 2 * 3 * 4\n", &options),
+            "# _Title_\n\n_a_ __b__ [_c_](x) a*b*c 2 * 3 * 4\n"
+        );
+
+        let options = FormatOptions {
+            emphasis_style: EmphasisStyle::Asterisk,
+            ..Default::default()
+        };
+        assert_eq!(
+            format("_a_ __b `c`__ snake_case_name \\_d_\n\n    _code_\n", &options),
+            "*a* **b `c`** snake_case_name \\_d_\n\n    _code_\n"
+        );
+    }
+
+    fn wrap(input: &str, width: usize) -> String {
+        format(
+            input,
+            &FormatOptions {
+                wrap: Wrap::Width(width),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn reflow_paragraph() {
+        assert_eq!(
+            wrap("the quick brown fox jumps over the lazy dog\n", 15),
+            "the quick brown\nfox jumps over\nthe lazy dog\n"
+        );
+        assert_eq!(
+            wrap("the quick\nbrown\nfox", 80),
+            "the quick brown fox"
+        );
+    }
+
+    #[test]
+    fn reflow_never() {
+        let options = FormatOptions {
+            wrap: Wrap::Never,
+            ..Default::default()
+        };
+        assert_eq!(
+            format("a long\nparagraph   split\nacross lines", &options),
+            "a long paragraph split across lines"
+        );
+    }
+
+    #[test]
+    fn reflow_keeps_inline_nodes_whole() {
+        assert_eq!(
+            wrap("see `some code` and [a link](https://example.com) here", 10),
+            "see\n`some code`\nand\n[a link](https://example.com)\nhere"
+        );
+        assert_eq!(
+            wrap("an ![alt text](image.png), <https://lib.rs>.", 5),
+            "an\n![alt text](image.png),\n<https://lib.rs>."
+        );
+    }
+
+    #[test]
+    fn reflow_avoids_block_markers() {
+        assert_eq!(wrap("issue # 5 is fixed", 6), "issue #\n5 is\nfixed");
+        assert_eq!(wrap("aaaa - bbbb", 4), "aaaa -\nbbbb");
+        assert_eq!(wrap("aaaa > bbbb", 4), "aaaa >\nbbbb");
+        assert_eq!(wrap("in 2023. we shipped", 4), "in 2023.\nwe\nshipped");
+        assert_eq!(wrap("version 1.5 is out", 7), "version\n1.5 is\nout");
+    }
+
+    #[test]
+    fn reflow_keeps_hard_breaks() {
+        assert_eq!(
+            wrap("first line  \nsecond line", 80),
+            "first line  \nsecond line"
+        );
+    }
+
+    #[test]
+    fn reflow_keeps_indented_code() {
+        let input = "text\n\n    fn main() {\n        println!(\"hi\");\n    }\n";
+        assert_eq!(wrap(input, 20), input);
+        assert_eq!(sentences(input), input);
+    }
+
+    #[test]
+    fn reflow_measures_columns() {
+        assert_eq!(wrap("日本語 日本語 日本語", 13), "日本語 日本語\n日本語");
+    }
+
+    fn sentences(input: &str) -> String {
+        format(
+            input,
+            &FormatOptions {
+                wrap: Wrap::Sentence,
+                abbreviations: vec!["fn.".to_string()],
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn semantic_line_breaks() {
+        assert_eq!(
+            sentences("One sentence. Another one! A third? Yes.\n"),
+            "One sentence.\nAnother one!\nA third?\nYes.\n"
+        );
+        assert_eq!(
+            sentences("A sentence that was\nwrapped mid-sentence. And\nanother."),
+            "A sentence that was wrapped mid-sentence.\nAnd another."
+        );
+        assert_eq!(
+            sentences("He said \"stop.\" Then (quietly) left."),
+            "He said \"stop.\"\nThen (quietly) left."
+        );
+    }
+
+    #[test]
+    fn semantic_line_breaks_skip_abbreviations() {
+        assert_eq!(
+            sentences("Ask Dr. Smith, e.g. about J. Doe. See fn. 2 and FN. 3."),
+            "Ask Dr. Smith, e.g. about J. Doe.\nSee fn. 2 and FN. 3."
+        );
+        assert_eq!(
+            sentences("E.g. this, or No. 5 at Acme Co. Ltd. on Mar. 3."),
+            "E.g. this, or No. 5 at Acme Co. Ltd. on Mar. 3."
+        );
+    }
+
+    #[test]
+    fn semantic_line_breaks_after_lowercase_words() {
+        assert_eq!(
+            sentences("I said no. Then I left. Meet at the co. Later, go home."),
+            "I said no.\nThen I left.\nMeet at the co.\nLater, go home."
+        );
+        assert_eq!(sentences("It was mar. St. Ives"), "It was mar.\nSt. Ives");
+    }
+
+    #[test]
+    fn semantic_line_breaks_ignore_links() {
+        assert_eq!(
+            sentences("See [the docs.](https://example.com) for more. Done."),
+            "See [the docs.](https://example.com) for more.\nDone."
+        );
+    }
+}
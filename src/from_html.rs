@@ -0,0 +1,406 @@
+//! Converting html back into markdown.
+
+use scraper::{ElementRef, Html, Node};
+
+use crate::{
+    ast::{escape_url, Block, Destination, Document, HeadingSyntax, Inline},
+    escape_markdown,
+    parser::headers::HeadingLevel,
+};
+
+/// Elements that only group other blocks, and don't need any markup of their own
+const CONTAINERS: &[&str] = &[
+    "html", "body", "div", "section", "article", "main", "header", "footer", "nav", "aside",
+];
+
+/// Build a document out of an html document or fragment.
+///
+/// Headings, paragraphs, lists, links, images, code, emphasis, line breaks and comments are
+/// converted; any other element is kept as raw html.  The result can be turned into a
+/// [`crate::Markdown`] with `Markdown::from`, and written out with [`crate::AsText`].
+pub fn parse_html(html: &str) -> Document {
+    let html = Html::parse_document(html);
+    let root = html.root_element();
+    let body = root
+        .children()
+        .filter_map(ElementRef::wrap)
+        .find(|e| e.value().name() == "body")
+        .unwrap_or(root);
+
+    let mut converter = Converter::default();
+    converter.convert_blocks(body);
+    converter.finish()
+}
+
+#[derive(Default)]
+struct Converter {
+    blocks: Vec<Block>,
+    /// Inline content that isn't inside of a block yet
+    inline: Vec<Inline>,
+}
+
+impl Converter {
+    fn push_block(&mut self, block: Block) {
+        self.flush_inline();
+        if !self.blocks.is_empty() {
+            self.blocks.push(Block::Separator {
+                span: None,
+                newlines: 2,
+            });
+        }
+        self.blocks.push(block);
+    }
+
+    fn flush_inline(&mut self) {
+        let inline = trim(std::mem::take(&mut self.inline));
+        if !inline.is_empty() {
+            self.push_block(Block::paragraph(inline));
+        }
+    }
+
+    fn finish(mut self) -> Document {
+        self.flush_inline();
+        if !self.blocks.is_empty() {
+            self.blocks.push(Block::Separator {
+                span: None,
+                newlines: 1,
+            });
+        }
+
+        Document {
+            front_matter: None,
+            blocks: self.blocks,
+        }
+    }
+
+    fn convert_blocks(&mut self, element: ElementRef<'_>) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => self.inline.push(self::text(text)),
+                Node::Comment(comment) => self.push_block(Block::Comment {
+                    span: None,
+                    html: format!("<!--{}-->", &**comment),
+                }),
+                Node::Element(_) => {
+                    let element = ElementRef::wrap(child).expect("the node is an element");
+                    self.convert_block(element);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn convert_block(&mut self, element: ElementRef<'_>) {
+        let name = element.value().name();
+        let heading = |level| Block::Heading {
+            span: None,
+            level,
+            syntax: HeadingSyntax::Atx,
+            children: trim(inlines(element)),
+        };
+
+        match name {
+            "h1" => self.push_block(heading(HeadingLevel::H1)),
+            "h2" => self.push_block(heading(HeadingLevel::H2)),
+            "h3" => self.push_block(heading(HeadingLevel::H3)),
+            "h4" => self.push_block(heading(HeadingLevel::H4)),
+            "h5" => self.push_block(heading(HeadingLevel::H5)),
+            "h6" => self.push_block(heading(HeadingLevel::H6)),
+            "p" => {
+                let children = trim(inlines(element));
+                if !children.is_empty() {
+                    self.push_block(Block::paragraph(children));
+                }
+            }
+            "ul" | "ol" => {
+                let mut lines = vec![];
+                list_lines(element, 0, &mut lines);
+                let mut children = vec![];
+                for line in lines.into_iter() {
+                    if !children.is_empty() {
+                        children.push(Inline::SoftBreak { span: None });
+                    }
+                    children.extend(line);
+                }
+                self.push_block(Block::Paragraph {
+                    span: None,
+                    children,
+                });
+            }
+            "pre" => {
+                let code: String = element.text().collect();
+                let mut children = vec![];
+                for line in code.trim_end_matches('\n').lines() {
+                    if !children.is_empty() {
+                        children.push(Inline::SoftBreak { span: None });
+                    }
+                    children.push(raw(&format!("    {line}")));
+                }
+                self.push_block(Block::Paragraph {
+                    span: None,
+                    children,
+                });
+            }
+            "hr" => self.push_block(Block::paragraph(vec![raw("* * *")])),
+            _ if CONTAINERS.contains(&name) => {
+                self.flush_inline();
+                self.convert_blocks(element);
+                self.flush_inline();
+            }
+            _ if is_inline(name) => self.inline.extend(inline(element)),
+            _ => self.push_block(Block::paragraph(vec![raw(&element.html())])),
+        }
+    }
+}
+
+/// Elements that are part of a paragraph, rather than being blocks of their own
+fn is_inline(name: &str) -> bool {
+    matches!(
+        name,
+        "a" | "img"
+            | "code"
+            | "strong"
+            | "b"
+            | "em"
+            | "i"
+            | "br"
+            | "span"
+            | "abbr"
+            | "kbd"
+            | "mark"
+            | "small"
+            | "sub"
+            | "sup"
+            | "u"
+            | "s"
+            | "del"
+            | "ins"
+            | "q"
+            | "cite"
+            | "time"
+    )
+}
+
+/// Text that's written out exactly as it is
+fn raw(text: &str) -> Inline {
+    Inline::Text {
+        span: None,
+        text: text.to_string(),
+    }
+}
+
+/// Html text, with whitespace collapsed and markdown's special characters escaped
+fn text(text: &str) -> Inline {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut last_was_space = false;
+    for c in text.chars() {
+        if c.is_whitespace() {
+            if !last_was_space {
+                collapsed.push(' ');
+            }
+            last_was_space = true;
+            continue;
+        }
+
+        last_was_space = false;
+        collapsed.push(c);
+    }
+
+    raw(&escape_markdown(&collapsed))
+}
+
+/// Drop whitespace from the start and end of some inline content.
+fn trim(mut inlines: Vec<Inline>) -> Vec<Inline> {
+    if let Some(Inline::Text { text, .. }) = inlines.first_mut() {
+        *text = text.trim_start().to_string();
+    }
+    if let Some(Inline::Text { text, .. }) = inlines.last_mut() {
+        *text = text.trim_end().to_string();
+    }
+    inlines.retain(|i| !matches!(i, Inline::Text { text, .. } if text.is_empty()));
+
+    inlines
+}
+
+/// The inline content of `element`.
+fn inlines(element: ElementRef<'_>) -> Vec<Inline> {
+    let mut inlines = vec![];
+    for child in element.children() {
+        match child.value() {
+            Node::Text(t) => inlines.push(text(t)),
+            Node::Comment(comment) => inlines.push(raw(&format!("<!--{}-->", &**comment))),
+            Node::Element(_) => {
+                let element = ElementRef::wrap(child).expect("the node is an element");
+                inlines.extend(inline(element));
+            }
+            _ => {}
+        }
+    }
+
+    inlines
+}
+
+/// Convert an element inside of a paragraph.
+fn inline(element: ElementRef<'_>) -> Vec<Inline> {
+    let wrapped = |marker: &str| {
+        let mut inlines = vec![raw(marker)];
+        inlines.extend(self::inlines(element));
+        inlines.push(raw(marker));
+        inlines
+    };
+
+    match element.value().name() {
+        "a" => match element.attr("href") {
+            Some(href) => vec![Inline::Link {
+                span: None,
+                destination: Destination::Inline(escape_url(href)),
+                title: element.attr("title").map(str::to_string),
+                children: inlines(element),
+            }],
+            None => inlines(element),
+        },
+        "img" => {
+            let alt = element
+                .attr("alt")
+                .unwrap_or_default()
+                .replace(['\r', '\n'], " ");
+            vec![Inline::Image {
+                span: None,
+                alt: escape_markdown(&alt).into_owned(),
+                destination: Destination::Inline(escape_url(
+                    element.attr("src").unwrap_or_default(),
+                )),
+                title: element.attr("title").map(str::to_string),
+            }]
+        }
+        "code" => vec![Inline::code(&element.text().collect::<String>())],
+        "strong" | "b" => wrapped("**"),
+        "em" | "i" => wrapped("*"),
+        "br" => vec![raw("  "), Inline::SoftBreak { span: None }],
+        "span" => inlines(element),
+        _ => vec![raw(&element.html())],
+    }
+}
+
+/// Write out the items of a list, one line each, with nested lists indented under their item.
+fn list_lines(list: ElementRef<'_>, depth: usize, lines: &mut Vec<Vec<Inline>>) {
+    let ordered = list.value().name() == "ol";
+    let items = list
+        .children()
+        .filter_map(ElementRef::wrap)
+        .filter(|e| e.value().name() == "li");
+    for (i, item) in items.enumerate() {
+        let marker = if ordered {
+            format!("{}. ", i + 1)
+        } else {
+            "- ".to_string()
+        };
+
+        let mut line = vec![raw(&format!("{:indent$}{marker}", "", indent = depth * 2))];
+        let mut nested = vec![];
+        for child in item.children() {
+            match child.value() {
+                Node::Text(t) => line.push(text(t)),
+                Node::Element(e) if matches!(e.name(), "ul" | "ol") => {
+                    nested.push(ElementRef::wrap(child).expect("the node is an element"))
+                }
+                Node::Element(e) if e.name() == "p" => line.extend(inlines(
+                    ElementRef::wrap(child).expect("the node is an element"),
+                )),
+                Node::Element(_) => line.extend(inline(
+                    ElementRef::wrap(child).expect("the node is an element"),
+                )),
+                _ => {}
+            }
+        }
+
+        let marker = line.remove(0);
+        let mut line = trim(line);
+        line.insert(0, marker);
+        lines.push(line);
+        for list in nested.into_iter() {
+            list_lines(list, depth + 1, lines);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{AsHtml, AsText, Markdown};
+
+    fn markdown(html: &str) -> String {
+        let document = parse_html(html);
+        let mut output = vec![];
+        Markdown::from(&document)
+            .write_as_text(&mut output)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn fragment() {
+        assert_eq!(
+            markdown(
+                "<h1>Release  notes</h1>\n<p>See <a href=\"https://x.y\" title=\"Docs\">the\n docs</a> \
+                 and <code>a_b</code>, <b>now</b> with <em>2*3</em>.<br>Next line</p>\
+                 <!-- keep -->"
+            ),
+            "# Release notes\n\n\
+             See [the docs](https://x.y \"Docs\") and `a_b`, **now** with *2\\*3*.  \nNext line\n\n\
+             <!-- keep -->\n"
+        );
+    }
+
+    #[test]
+    fn document() {
+        assert_eq!(
+            markdown(
+                "<!DOCTYPE html><html><head><title>x</title></head><body>\
+                 <div><p># not a heading</p><img src=\"a.png\" alt=\"A\"></div>\
+                 <ul><li>one<ul><li>two</li></ul></li><li><p>three</p></li></ul>\
+                 <ol><li>first</li></ol>\
+                 <pre><code>fn main() {\n}\n</code></pre>\
+                 </body></html>"
+            ),
+            "\\# not a heading\n\n![A](a.png)\n\n\
+             - one\n  - two\n- three\n\n\
+             1. first\n\n    fn main() {\n    }\n"
+        );
+    }
+
+    #[test]
+    fn unsupported_elements() {
+        assert_eq!(
+            markdown(
+                "<table><tr><td>x</td></tr></table><p>a <kbd>b</kbd> <video src=v></video></p>"
+            ),
+            "<table><tbody><tr><td>x</td></tr></tbody></table>\n\n\
+             a <kbd>b</kbd> <video src=\"v\"></video>\n"
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            markdown(
+                "<p><code>a`b</code> <a href=\"/my page (v2)\">v2</a> \
+                 <img src=\"a b.png\" alt=\"a ] b\"></p>"
+            ),
+            "a\\`b [v2](/my%20page%20%28v2%29) ![a \\] b](a%20b.png)\n"
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let html = "<p>x<br>- not a list<br>1. nor this<br># nor this</p>\
+                    <p><code>a`b</code> <a href=\"/my page (v2)\">v2</a> \
+                    <img src=\"a.png\" alt=\"a ] b\"></p>";
+        assert_eq!(
+            Markdown::parse(&markdown(html)).to_html(),
+            "<p>x<br>\n- not a list<br>\n1. nor this<br>\n# nor this</p>\n\
+             <p>a`b <a href=\"/my%20page%20%28v2%29\">v2</a> \
+             <img src=\"a.png\" alt=\"a ] b\"/>\n</p>"
+        );
+    }
+}
@@ -0,0 +1,234 @@
+//! Wrapping html output in a complete document.
+
+use std::io;
+
+use crate::{
+    escape_html,
+    parser::{block::Block, headers::HeadingLevel},
+    render::{RenderOptions, Renderer},
+    slug::plain_text,
+    toc::TocOptions,
+    HtmlOptions, Markdown,
+};
+
+/// The template used for standalone documents when no other template is given.
+pub const DEFAULT_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{{title}}</title>
+{{stylesheets}}</head>
+<body>
+{{body}}
+</body>
+</html>
+"#;
+
+/// Settings for writing a complete html document.
+///
+/// Templates are html with placeholders, which get replaced when the document is written:
+///
+/// * `{{title}}`: the title of the document
+/// * `{{body}}`: the document itself
+/// * `{{toc}}`: a table of contents, as a `<nav>`
+/// * `{{stylesheets}}`: a `<link>` for each stylesheet
+///
+/// Anything else between `{{` and `}}` is left alone.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct Standalone {
+    /// The title of the document.  Defaults to [`Markdown::title`].
+    pub title: Option<String>,
+    /// Urls of stylesheets to link to
+    pub stylesheets: Vec<String>,
+    /// A template to use instead of [`DEFAULT_TEMPLATE`]
+    pub template: Option<String>,
+    /// Which headings go into `{{toc}}`
+    pub toc: TocOptions,
+}
+
+impl<'source> Markdown<'source> {
+    /// The title of this document: the `title` from its front matter, or else the text of its
+    /// first H1.
+    pub fn title(&self) -> Option<String> {
+        if let Some(title) = self.metadata("title") {
+            return Some(title.to_string());
+        }
+
+        self.blocks.iter().find_map(|block| match block {
+            Block::Heading(h) if h.level() == HeadingLevel::H1 => {
+                Some(plain_text(h.text()).trim().to_string())
+            }
+            _ => None,
+        })
+    }
+}
+
+impl Renderer for HtmlOptions {
+    fn render<Writer: io::Write>(
+        &self,
+        md: &Markdown<'_>,
+        output: &mut Writer,
+        options: &RenderOptions,
+    ) -> io::Result<()> {
+        match &self.standalone {
+            Some(standalone) => write_document(md, output, self, options, standalone),
+            None => md.write_html_fragment(output, self, options),
+        }
+    }
+}
+
+/// Write `md` as a complete html document.
+fn write_document<Writer: io::Write>(
+    md: &Markdown<'_>,
+    output: &mut Writer,
+    options: &HtmlOptions,
+    render_options: &RenderOptions,
+    standalone: &Standalone,
+) -> io::Result<()> {
+    let template = standalone.template.as_deref().unwrap_or(DEFAULT_TEMPLATE);
+
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}").map(|end| start + end) else {
+            break;
+        };
+        output.write_all(&rest.as_bytes()[..start])?;
+
+        match rest[start + 2..end].trim() {
+            "title" => {
+                let title = standalone.title.clone().or_else(|| md.title());
+                write!(
+                    output,
+                    "{}",
+                    escape_html(title.as_deref().unwrap_or_default())
+                )?;
+            }
+            "body" => md.write_html_fragment(output, options, render_options)?,
+            "toc" => md.toc(&standalone.toc).render_html(output, render_options)?,
+            "stylesheets" => {
+                for href in standalone.stylesheets.iter() {
+                    writeln!(
+                        output,
+                        "<link rel=\"stylesheet\" href=\"{}\">",
+                        escape_html(href)
+                    )?;
+                }
+            }
+            _ => output.write_all(&rest.as_bytes()[start..end + 2])?,
+        }
+        rest = &rest[end + 2..];
+    }
+
+    output.write_all(rest.as_bytes())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        format::{FormatOptions, LinkStyle},
+        AsHtml, AsText,
+    };
+
+    fn standalone(input: &str, standalone: Standalone) -> String {
+        let md = Markdown::parse(input);
+        let mut output = vec![];
+        md.write_html_with(
+            &mut output,
+            &HtmlOptions {
+                standalone: Some(standalone),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn default_template() {
+        let html = standalone(
+            "# Fish & Chips\n\nA recipe",
+            Standalone {
+                stylesheets: vec!["style.css".to_string()],
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            html,
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+             <title>Fish &amp; Chips</title>\n\
+             <link rel=\"stylesheet\" href=\"style.css\">\n</head>\n<body>\n\
+             <h1 id=\"fish--chips\">Fish &amp; Chips</h1>\n<p>A recipe</p>\n</body>\n</html>\n"
+        );
+    }
+
+    #[test]
+    fn custom_template() {
+        let html = standalone(
+            "## Usage\n\nRun it",
+            Standalone {
+                title: Some("Manual".to_string()),
+                template: Some("{{ title }}|{{toc}}|{{body}}|{{unknown}}|{{".to_string()),
+                ..Default::default()
+            },
+        );
+        assert_eq!(
+            html,
+            "Manual|<nav class=\"toc\"><ul><li><a href=\"#usage\">Usage</a></li></ul></nav>|\
+             <h2 id=\"usage\">Usage</h2>\n<p>Run it</p>|{{unknown}}|{{"
+        );
+    }
+
+    #[test]
+    fn title() {
+        let md = Markdown::parse("## Not this\n\n# The `title`");
+        assert_eq!(md.title().as_deref(), Some("The title"));
+        assert_eq!(Markdown::parse("text").title(), None);
+
+        #[cfg(feature = "front-matter")]
+        {
+            let md = Markdown::parse("---\ntitle: From metadata\n---\n\n# The title");
+            assert_eq!(md.title().as_deref(), Some("From metadata"));
+        }
+    }
+
+    #[test]
+    fn reference_links() {
+        let md = Markdown::parse(
+            "See [the docs][docs], [Docs][] and ![a logo][logo] but not [this][nowhere].\n\n\
+             [docs]: /docs\n[logo]: /logo.png\n",
+        );
+        assert_eq!(
+            md.to_html(),
+            "<p>See <a href=\"/docs\">the docs</a>, <a href=\"/docs\">Docs</a> and \
+             <img src=\"/logo.png\" alt=\"a logo\"/> but not [this][nowhere].</p>\n\n\n"
+        );
+
+        // the same document with its links rewritten to reference style writes the same html
+        let input = "See [the docs](/docs) and ![a logo](/logo.png).\n";
+        let mut md = Markdown::parse(input);
+        let inline = md.to_html();
+        md.format(&FormatOptions {
+            link_style: LinkStyle::Reference,
+            ..Default::default()
+        });
+        assert_ne!(md.to_markdown(), input);
+        assert!(md.to_html().starts_with(inline.trim_end_matches("</p>")));
+    }
+
+    #[test]
+    fn escaping() {
+        let md = Markdown::parse(
+            "R&D &amp; Q&#38;A, `a && b` and [a search](/find?q=\"a&b\").\n\n\
+             ![Tom & Jerry](/t&j.png)",
+        );
+        assert_eq!(
+            md.to_html(),
+            "<p>R&amp;D &amp; Q&#38;A, <code>a &amp;&amp; b</code> and \
+             <a href=\"/find?q=&quot;a&amp;b&quot;\">a search</a>.</p>\n\
+             <p><img src=\"/t&amp;j.png\" alt=\"Tom &amp; Jerry\"/></p>"
+        );
+    }
+}
@@ -0,0 +1,265 @@
+//! Writing a document as LaTeX.
+
+use std::{fmt, io, str::FromStr};
+
+use crate::{
+    escape_latex,
+    parser::{block::Block, headers::HeadingLevel},
+    plain::link_targets,
+    render::{RenderOptions, Renderer},
+    AsLatex, Markdown,
+};
+
+/// The LaTeX sectioning commands, from the outermost to the innermost.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Sectioning {
+    Part,
+    Chapter,
+    #[default]
+    Section,
+    Subsection,
+    Subsubsection,
+    Paragraph,
+    Subparagraph,
+}
+
+impl Sectioning {
+    const ALL: [Sectioning; 7] = [
+        Sectioning::Part,
+        Sectioning::Chapter,
+        Sectioning::Section,
+        Sectioning::Subsection,
+        Sectioning::Subsubsection,
+        Sectioning::Paragraph,
+        Sectioning::Subparagraph,
+    ];
+
+    /// The command that starts a section at this level, without the backslash
+    pub fn command(self) -> &'static str {
+        match self {
+            Sectioning::Part => "part",
+            Sectioning::Chapter => "chapter",
+            Sectioning::Section => "section",
+            Sectioning::Subsection => "subsection",
+            Sectioning::Subsubsection => "subsubsection",
+            Sectioning::Paragraph => "paragraph",
+            Sectioning::Subparagraph => "subparagraph",
+        }
+    }
+
+    /// The level `depth` steps further in than this one.  Anything past `\subparagraph` stays
+    /// a `\subparagraph`.
+    pub fn nested(self, depth: usize) -> Self {
+        let index = Self::ALL
+            .iter()
+            .position(|&s| s == self)
+            .unwrap_or_default();
+        Self::ALL[(index + depth).min(Self::ALL.len() - 1)]
+    }
+}
+
+impl FromStr for Sectioning {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|sectioning| sectioning.command() == s)
+            .ok_or_else(|| {
+                format!(
+                    "expected one of `part`, `chapter`, `section`, `subsection`, \
+                     `subsubsection`, `paragraph` or `subparagraph`, found `{s}`"
+                )
+            })
+    }
+}
+
+impl fmt::Display for Sectioning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.command())
+    }
+}
+
+/// Settings for writing a [`Markdown`] document as LaTeX.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct LatexOptions {
+    /// The sectioning command used for H1 headings.  Deeper headings use the commands after it.
+    pub base_level: Sectioning,
+    /// Wrap the output in a complete document, with a preamble, instead of writing a fragment
+    pub standalone: bool,
+    /// The title of a standalone document.  Defaults to [`Markdown::title`].
+    pub title: Option<String>,
+}
+
+impl<'source> Markdown<'source> {
+    /// Write this document as LaTeX.  Links need the `hyperref` package and images need
+    /// `graphicx`; standalone documents load both.
+    pub fn write_latex_with<Writer: io::Write>(
+        &self,
+        output: &mut Writer,
+        options: &LatexOptions,
+    ) -> io::Result<()> {
+        self.render(options, output, &RenderOptions::default())
+    }
+
+    /// Write this document as LaTeX, following the settings every format shares.
+    fn render_latex<Writer: io::Write>(
+        &self,
+        output: &mut Writer,
+        options: &LatexOptions,
+        render_options: &RenderOptions,
+    ) -> io::Result<()> {
+        if options.standalone {
+            let class = if options.base_level < Sectioning::Section {
+                "report"
+            } else {
+                "article"
+            };
+            writeln!(output, "\\documentclass{{{class}}}")?;
+            writeln!(output, "\\usepackage[utf8]{{inputenc}}")?;
+            writeln!(output, "\\usepackage{{graphicx}}")?;
+            writeln!(output, "\\usepackage{{hyperref}}")?;
+            let title = options.title.clone().or_else(|| self.title());
+            if let Some(title) = &title {
+                writeln!(output, "\\title{{{}}}", escape_latex(title))?;
+            }
+            writeln!(output, "\\begin{{document}}")?;
+            if title.is_some() {
+                writeln!(output, "\\maketitle")?;
+            }
+        }
+
+        // `\maketitle` already writes the heading the title was taken from
+        let title_from_heading =
+            options.standalone && options.title.is_none() && self.metadata("title").is_none();
+        let title_heading = self
+            .blocks
+            .iter()
+            .position(|b| matches!(b, Block::Heading(h) if h.level() == HeadingLevel::H1))
+            .filter(|_| title_from_heading);
+
+        let definitions = link_targets(&self.blocks);
+        // blocks that write nothing leave out the blank lines after them too
+        let mut skipped = false;
+        for (i, b) in self.blocks.iter().enumerate() {
+            match b {
+                _ if Some(i) == title_heading => {
+                    skipped = true;
+                    continue;
+                }
+                Block::LinkDefinition(_) | Block::Comment(..) => {
+                    skipped = true;
+                    continue;
+                }
+                Block::Separator(..) if skipped => continue,
+                Block::Heading(h) => {
+                    h.render_latex(output, options.base_level, &definitions, render_options)?
+                }
+                _ => b.render_latex(output, &definitions, render_options)?,
+            }
+            skipped = false;
+        }
+
+        if options.standalone {
+            writeln!(output)?;
+            writeln!(output, "\\end{{document}}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'source> AsLatex for Markdown<'source> {
+    fn write_latex<Writer: io::Write>(&self, output: &mut Writer) -> io::Result<()> {
+        self.write_latex_with(output, &LatexOptions::default())
+    }
+}
+
+impl Renderer for LatexOptions {
+    fn render<Writer: io::Write>(
+        &self,
+        md: &Markdown<'_>,
+        output: &mut Writer,
+        options: &RenderOptions,
+    ) -> io::Result<()> {
+        md.render_latex(output, self, options)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn latex(input: &str, options: &LatexOptions) -> String {
+        let md = Markdown::parse(input);
+        let mut output = vec![];
+        md.write_latex_with(&mut output, options).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn fragment() {
+        let input = "# Design & scope\n\n## 100% *done*\n\n\
+                     See [the spec](https://x.y/a#b) or <https://x.y>, `a_b{}` costs $5.\n\n\
+                     ![diagram](img/arch.png)\n\n    fn main() {}\n";
+        assert_eq!(
+            latex(input, &LatexOptions::default()),
+            "\\section{Design \\& scope}\n\n\\subsection{100\\% done}\n\n\
+             See \\href{https://x.y/a\\#b}{the spec} or \\url{https://x.y}, \
+             \\texttt{a\\_b\\{\\}} costs \\$5.\n\n\
+             \\includegraphics{img/arch.png}\n\n\
+             \\begin{verbatim}\nfn main() {}\n\\end{verbatim}\n"
+        );
+    }
+
+    #[test]
+    fn reference_links() {
+        let input = "See [the spec][spec], [Spec][] and [nothing][none].\n\n\
+                     ![a diagram][arch]\n\n\
+                     [spec]: https://x.y/a#b\n[arch]: img/100%.png\n";
+        assert_eq!(
+            latex(input, &LatexOptions::default()),
+            "See \\href{https://x.y/a\\#b}{the spec}, \\href{https://x.y/a\\#b}{Spec} and \
+             nothing.\n\n\\includegraphics{img/100\\%.png}\n\n"
+        );
+    }
+
+    #[test]
+    fn base_level() {
+        let options = LatexOptions {
+            base_level: Sectioning::Chapter,
+            ..Default::default()
+        };
+        assert_eq!(
+            latex("# One\n\n## Two\n\n###### Six", &options),
+            "\\chapter{One}\n\n\\section{Two}\n\n\\subparagraph{Six}"
+        );
+    }
+
+    #[test]
+    fn sectioning_from_str() {
+        assert_eq!("chapter".parse(), Ok(Sectioning::Chapter));
+        assert!("chapters".parse::<Sectioning>().is_err());
+    }
+
+    #[test]
+    fn standalone() {
+        let options = LatexOptions {
+            standalone: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            latex("# Notes\n\nText ~ ^ \\", &options),
+            "\\documentclass{article}\n\\usepackage[utf8]{inputenc}\n\\usepackage{graphicx}\n\
+             \\usepackage{hyperref}\n\\title{Notes}\n\\begin{document}\n\\maketitle\n\
+             Text \\textasciitilde{} \\textasciicircum{} \\textbackslash{}\n\\end{document}\n"
+        );
+
+        let options = LatexOptions {
+            title: Some("Other".to_string()),
+            ..options
+        };
+        assert!(latex("# Notes\n\nText", &options)
+            .contains("\\title{Other}\n\\begin{document}\n\\maketitle\n\\section{Notes}\n\nText"));
+    }
+}
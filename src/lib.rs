@@ -1,17 +1,233 @@
-use std::{borrow::Cow, io};
+use std::{borrow::Cow, fmt, io};
 
 use once_cell::sync::OnceCell;
 use regex::{Regex, RegexBuilder};
-use winnow::{multi::many1, FinishIResult, Parser};
+use winnow::{error::ErrMode, Parser};
 
+use parser::{block::Block, paragraphs::Paragraph, util::MarkdownText, ParseOptions};
+use render::RenderOptions;
+use span::{LineIndex, Locate, Span};
+use warning::Warning;
+
+pub mod ansi;
+pub mod ast;
+pub mod format;
+#[cfg(feature = "from-html")]
+pub mod from_html;
+pub mod html;
+pub mod latex;
 pub mod parser;
+pub mod plain;
+pub mod render;
+pub mod roff;
+pub mod slug;
+pub mod span;
+pub mod toc;
+pub mod visit;
+pub mod warning;
 
 pub trait AsText {
     fn write_as_text<Writer: io::Write>(&self, output: &mut Writer) -> io::Result<()>;
+
+    /// Write this as markdown to a `String`, a `Formatter` or anything else that's `fmt::Write`.
+    fn write_as_text_fmt<Writer: fmt::Write + ?Sized>(&self, output: &mut Writer) -> fmt::Result {
+        write_to_fmt(output, |output| self.write_as_text(output))
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut output = String::new();
+        self.write_as_text_fmt(&mut output)
+            .expect("writing to a String never fails");
+        output
+    }
 }
 
 pub trait AsHtml {
     fn write_html<Writer: io::Write>(&self, output: &mut Writer) -> io::Result<()>;
+
+    /// Write this as html to anything that's `fmt::Write`.
+    fn write_html_fmt<Writer: fmt::Write + ?Sized>(&self, output: &mut Writer) -> fmt::Result {
+        write_to_fmt(output, |output| self.write_html(output))
+    }
+
+    fn to_html(&self) -> String {
+        let mut output = String::new();
+        self.write_html_fmt(&mut output)
+            .expect("writing to a String never fails");
+        output
+    }
+}
+
+pub trait AsLatex {
+    fn write_latex<Writer: io::Write>(&self, output: &mut Writer) -> io::Result<()>;
+
+    /// Write this as LaTeX to anything that's `fmt::Write`.
+    fn write_latex_fmt<Writer: fmt::Write + ?Sized>(&self, output: &mut Writer) -> fmt::Result {
+        write_to_fmt(output, |output| self.write_latex(output))
+    }
+
+    fn to_latex(&self) -> String {
+        let mut output = String::new();
+        self.write_latex_fmt(&mut output)
+            .expect("writing to a String never fails");
+        output
+    }
+}
+
+/// Passes what's written to it on to a `fmt::Write`, so the writers, which all take an
+/// `io::Write`, can write to one too.
+pub(crate) struct FmtWriter<'a, Writer: ?Sized> {
+    output: &'a mut Writer,
+    /// The start of a character that was cut off at the end of the last write
+    partial: Vec<u8>,
+}
+
+impl<'a, Writer: fmt::Write + ?Sized> io::Write for FmtWriter<'a, Writer> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.partial.extend_from_slice(buf);
+        let valid = match std::str::from_utf8(&self.partial) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let text = std::str::from_utf8(&self.partial[..valid]).expect("checked to be valid above");
+        self.output.write_str(text).map_err(io::Error::other)?;
+        self.partial.drain(..valid);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Run `write` with an `io::Write` that writes to `output`.
+pub(crate) fn write_to_fmt<Writer: fmt::Write + ?Sized>(
+    output: &mut Writer,
+    write: impl FnOnce(&mut FmtWriter<'_, Writer>) -> io::Result<()>,
+) -> fmt::Result {
+    let mut writer = FmtWriter {
+        output,
+        partial: vec![],
+    };
+    write(&mut writer).map_err(|_| fmt::Error)?;
+    match writer.partial.is_empty() {
+        true => Ok(()),
+        false => Err(fmt::Error),
+    }
+}
+
+/// Escape the characters in `text` that have a special meaning in html.
+pub(crate) fn escape_html(text: &str) -> Cow<'_, str> {
+    if !text.contains(['&', '<', '>', '"', '\'']) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Escape each `&` in `text` that doesn't already start a character reference like `&amp;` or
+/// `&#39;`.  Anything else, like inline html, is left as it is.
+pub(crate) fn escape_ampersands(text: &str) -> Cow<'_, str> {
+    static REFERENCE_RE: OnceCell<Regex> = OnceCell::new();
+    if !text.contains('&') {
+        return Cow::Borrowed(text);
+    }
+
+    let reference = REFERENCE_RE.get_or_init(|| {
+        Regex::new(r"^&(?:[A-Za-z][A-Za-z0-9]*|#[0-9]{1,7}|#[xX][0-9A-Fa-f]{1,6});")
+            .expect("the character reference regex is valid")
+    });
+    let mut escaped = String::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        match c {
+            '&' if !reference.is_match(&text[i..]) => escaped.push_str("&amp;"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Escape `text` to go inside a double-quoted html attribute, like a link's `href`.
+pub(crate) fn escape_attribute(text: &str) -> Cow<'_, str> {
+    match escape_ampersands(text) {
+        escaped if escaped.contains('"') => Cow::Owned(escaped.replace('"', "&quot;")),
+        escaped => escaped,
+    }
+}
+
+/// Escape the characters in `text` that would otherwise be read as inline markdown.
+pub(crate) fn escape_markdown(text: &str) -> Cow<'_, str> {
+    if !text.contains(['\\', '*', '_', '`', '[', ']', '<']) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']' | '<') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    Cow::Owned(escaped)
+}
+
+/// Replace backslash escapes in `text` with the characters they escape.
+pub(crate) fn unescape_markdown(text: &str) -> Cow<'_, str> {
+    if !text.contains('\\') {
+        return Cow::Borrowed(text);
+    }
+
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => unescaped.push(chars.next_if(char::is_ascii_punctuation).unwrap_or('\\')),
+            c => unescaped.push(c),
+        }
+    }
+    Cow::Owned(unescaped)
+}
+
+/// Escape the characters in `text` that have a special meaning in LaTeX.
+pub(crate) fn escape_latex(text: &str) -> Cow<'_, str> {
+    if !text.contains(['#', '$', '%', '&', '~', '_', '^', '\\', '{', '}']) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '#' | '$' | '%' | '&' | '_' | '{' | '}' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '~' => escaped.push_str("\\textasciitilde{}"),
+            '^' => escaped.push_str("\\textasciicircum{}"),
+            '\\' => escaped.push_str("\\textbackslash{}"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Escape a url for use in `\href` or `\url`, where only `#`, `%` and `\` need escaping.
+pub(crate) fn escape_latex_url(url: &str) -> String {
+    url.replace('\\', "\\\\")
+        .replace('#', "\\#")
+        .replace('%', "\\%")
 }
 
 pub fn cleanup(data: &'_ str, tab_width: usize) -> Cow<'_, str> {
@@ -51,8 +267,7 @@ pub fn cleanup(data: &'_ str, tab_width: usize) -> Cow<'_, str> {
             let Some(mut line) = iter.next().map(|s| s.to_string()) else { return "".to_string() };
             for x in iter {
                 let amount = (tab_width - x.chars().count()) % tab_width;
-                std::iter::repeat(" ")
-                    .take(amount)
+                std::iter::repeat_n(" ", amount)
                     .for_each(|s| line.push_str(s));
             }
 
@@ -75,22 +290,134 @@ pub fn cleanup(data: &'_ str, tab_width: usize) -> Cow<'_, str> {
 
 /// A parsed representation of a Markdown file
 pub struct Markdown<'source> {
+    front_matter: Option<parser::front_matter::FrontMatter<'source>>,
     blocks: Vec<parser::block::Block<'source>>,
+    line_index: LineIndex,
+    warnings: Vec<Warning>,
 }
 
 impl<'source> Markdown<'source> {
-    pub fn parse(input: &'source str) -> color_eyre::Result<Self> {
-        many1(parser::block::parse_block)
-            .context("markdown text")
-            .map(|blocks| Markdown { blocks })
-            .parse_next(input)
-            .finish()
-            .map_err(|e| color_eyre::eyre::eyre!("parsing error: {:?}", e))
+    /// Parse a document.  This never fails: anything that can't be parsed is kept as text, and
+    /// noted in [`Markdown::warnings`].
+    pub fn parse(input: &'source str) -> Self {
+        Self::parse_with(input, &ParseOptions::default())
+    }
+
+    /// Parse a document the way `options` say to, e.g. as CommonMark.
+    pub fn parse_with(input: &'source str, options: &ParseOptions) -> Self {
+        Self::parse_with_index(input, LineIndex::new(input), options)
+    }
+
+    /// Parse `input`, the text that [`cleanup`] made from `original`.  Warnings and
+    /// [`Markdown::line_index`] report positions in `original`.
+    pub fn parse_cleaned(input: &'source str, original: &str) -> Self {
+        Self::parse_cleaned_with(input, original, &ParseOptions::default())
+    }
+
+    /// Like [`Markdown::parse_cleaned`], but the way `options` say to.
+    pub fn parse_cleaned_with(
+        input: &'source str,
+        original: &str,
+        options: &ParseOptions,
+    ) -> Self {
+        Self::parse_with_index(input, LineIndex::with_cleanup(original, input), options)
+    }
+
+    fn parse_with_index(
+        input: &'source str,
+        line_index: LineIndex,
+        options: &ParseOptions,
+    ) -> Self {
+        let (mut rest, front_matter) = match parser::front_matter::parse_front_matter(input) {
+            Ok((rest, front_matter)) if options.front_matter => (rest, Some(front_matter)),
+            _ => (input, None),
+        };
+
+        let mut blocks = vec![];
+        let mut warnings = vec![];
+        if let Some((offset, message)) = front_matter.as_ref().and_then(|f| f.error()) {
+            warnings.push(Warning::new(message, offset, vec!["front matter"], &line_index));
+        }
+        let mut parse_block = parser::block::parse_block(options).context("markdown text");
+        while !rest.is_empty() {
+            match parse_block.parse_next(rest) {
+                Ok((remaining, block)) if remaining.len() < rest.len() => {
+                    blocks.push(block);
+                    rest = remaining;
+                }
+                result => {
+                    if let Err(ErrMode::Backtrack(e) | ErrMode::Cut(e)) = result {
+                        warnings.push(Warning::unparsed(e, input, &line_index));
+                    }
+                    let (block, remaining) = skip_line(rest);
+                    blocks.push(block);
+                    rest = remaining;
+                }
+            }
+        }
+
+        #[cfg(feature = "gfm")]
+        if options.autolink_literals {
+            parser::autolinks::link_literals(&mut blocks);
+        }
+        blocks.locate(input);
+        Markdown {
+            front_matter,
+            blocks,
+            line_index,
+            warnings,
+        }
+    }
+
+    /// The blocks this document is made of, in order
+    pub fn blocks(&self) -> &[Block<'source>] {
+        &self.blocks
+    }
+
+    /// Every piece of inline content in this document, in order, including the text of links
+    pub fn inlines(&self) -> impl Iterator<Item = &MarkdownText<'source>> {
+        self.blocks.iter().flat_map(|block| MarkdownText::walk(block.text()))
+    }
+
+    /// Anything questionable found while parsing, like front matter that couldn't be read
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Turns the spans of this document's nodes into lines and columns.
+    pub fn line_index(&self) -> &LineIndex {
+        &self.line_index
+    }
+
+    /// The front matter at the start of this document, if there is any
+    pub fn front_matter(&self) -> Option<&parser::front_matter::FrontMatter<'source>> {
+        self.front_matter.as_ref()
+    }
+
+    /// Look up a top-level key in this document's front matter.
+    pub fn metadata(&self, key: &str) -> Option<&str> {
+        self.front_matter
+            .as_ref()
+            .and_then(|front_matter| front_matter.metadata.get(key))
+            .map(String::as_str)
+    }
+
+    /// Copy everything this document borrows from its source, so it can outlive it.
+    pub fn into_owned(self) -> Markdown<'static> {
+        Markdown {
+            front_matter: self.front_matter.map(|f| f.into_owned()),
+            blocks: self.blocks.into_iter().map(Block::into_owned).collect(),
+            line_index: self.line_index,
+            warnings: self.warnings,
+        }
     }
 }
 
 impl<'source> AsText for Markdown<'source> {
     fn write_as_text<Writer: io::Write>(&self, output: &mut Writer) -> io::Result<()> {
+        if let Some(front_matter) = &self.front_matter {
+            write!(output, "{}", front_matter.raw)?;
+        }
         for b in self.blocks.iter() {
             b.write_as_text(output)?;
         }
@@ -99,12 +426,184 @@ impl<'source> AsText for Markdown<'source> {
     }
 }
 
-impl<'source> AsHtml for Markdown<'source> {
-    fn write_html<Writer: io::Write>(&self, output: &mut Writer) -> io::Result<()> {
+/// Writes the document back out as markdown.
+impl<'source> fmt::Display for Markdown<'source> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_as_text_fmt(f)
+    }
+}
+
+/// Settings for writing a [`Markdown`] document as html.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct HtmlOptions {
+    /// Add a link to each heading inside the heading itself, so readers can grab a link to it
+    pub heading_self_links: bool,
+    /// Wrap the output in a complete html document, instead of writing a fragment
+    pub standalone: Option<html::Standalone>,
+}
+
+impl<'source> Markdown<'source> {
+    /// Write this document as html.  Each heading gets the `id` from [`Markdown::heading_anchors`].
+    pub fn write_html_with<Writer: io::Write>(
+        &self,
+        output: &mut Writer,
+        options: &HtmlOptions,
+    ) -> io::Result<()> {
+        self.render(options, output, &RenderOptions::default())
+    }
+
+    /// Write the blocks of this document as html, without wrapping them in a document.
+    pub(crate) fn write_html_fragment<Writer: io::Write>(
+        &self,
+        output: &mut Writer,
+        options: &HtmlOptions,
+        render_options: &RenderOptions,
+    ) -> io::Result<()> {
+        let definitions = plain::link_targets(&self.blocks);
+        let mut anchors = self.heading_anchors().into_iter();
         for b in self.blocks.iter() {
-            b.write_html(output)?;
+            match b {
+                parser::block::Block::Heading(h) => {
+                    let anchor = anchors.next().expect("there's an anchor for every heading");
+                    let id = format!("{}{}", render_options.heading_id_prefix, anchor.id);
+                    h.render_html(
+                        output,
+                        Some(&id),
+                        options.heading_self_links,
+                        &definitions,
+                        render_options,
+                    )?;
+                }
+                _ => b.render_html(output, &definitions, render_options)?,
+            }
         }
 
         Ok(())
     }
 }
+
+impl<'source> AsHtml for Markdown<'source> {
+    fn write_html<Writer: io::Write>(&self, output: &mut Writer) -> io::Result<()> {
+        self.write_html_with(output, &HtmlOptions::default())
+    }
+}
+
+/// Keep the first line of `input`, which couldn't be parsed, as a paragraph of text, so parsing
+/// can carry on from the next one.  An empty line is kept as a separator instead.
+fn skip_line(input: &str) -> (Block<'_>, &str) {
+    match input.find('\n') {
+        Some(0) => {
+            let (newline, rest) = input.split_at(1);
+            (Block::Separator(1, Span::of(newline)), rest)
+        }
+        end => {
+            let (line, rest) = input.split_at(end.unwrap_or(input.len()));
+            let paragraph = Paragraph {
+                text: vec![MarkdownText::text(line)],
+                span: Span::of(line),
+            };
+            (Block::Paragraph(paragraph), rest)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn owned_outlives_source() {
+        let input = "---\ntitle: Notes\n---\n# Notes\n\nSee [the docs][docs], `code` and ![a](b.png).\n\n\
+                     [docs]: /docs \"Docs\"\n";
+        let source = input.to_string();
+        let md = Markdown::parse(&source).into_owned();
+        drop(source);
+
+        let md = std::thread::spawn(move || md).join().unwrap();
+        #[cfg(feature = "front-matter")]
+        assert_eq!(md.metadata("title"), Some("Notes"));
+        let mut output = vec![];
+        md.write_as_text(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), input);
+    }
+
+    #[test]
+    fn strings() {
+        let md = Markdown::parse("# Café\n\nSee [the *menu*](/menu).\n");
+        assert_eq!(md.to_markdown(), "# Café\n\nSee [the *menu*](/menu).\n");
+        assert_eq!(format!("{md}"), md.to_markdown());
+        assert_eq!(format!("{}", md.blocks()[0]), "# Café");
+        assert_eq!(md.blocks()[2].to_html(), "<p>See <a href=\"/menu\">the *menu*</a>.\n</p>");
+
+        let link = md.inlines().find(|t| matches!(t, MarkdownText::Link(_)));
+        assert_eq!(link.unwrap().to_string(), "[the *menu*](/menu)");
+    }
+
+    #[test]
+    fn characters_split_across_writes() {
+        let mut output = String::new();
+        write_to_fmt(&mut output, |w| {
+            let bytes = "é!".as_bytes();
+            io::Write::write_all(w, &bytes[..1])?;
+            io::Write::write_all(w, &bytes[1..])
+        })
+        .unwrap();
+        assert_eq!(output, "é!");
+
+        let result = write_to_fmt(&mut String::new(), |w| io::Write::write_all(w, &[0xc3]));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn skip_line() {
+        let input = "\nnext line\n";
+        let (block, rest) = super::skip_line(input);
+        assert!(matches!(block, Block::Separator(1, _)));
+        assert_eq!(rest, "next line\n");
+
+        let (block, rest) = super::skip_line(rest);
+        assert_eq!(block.to_markdown(), "next line");
+        assert_eq!(rest, "\n");
+
+        let (block, rest) = super::skip_line("last line");
+        assert_eq!(block.to_markdown(), "last line");
+        assert_eq!(rest, "");
+    }
+
+    #[test]
+    fn dialects() {
+        use parser::Dialect;
+
+        let input = "---\ntitle: Notes\n---\n\n#Notes\n\nSee https://x.y/z.\n";
+        let parse = |dialect: Dialect| Markdown::parse_with(input, &dialect.into());
+        let headings = |md: &Markdown<'_>| -> Vec<String> {
+            md.blocks()
+                .iter()
+                .filter_map(|b| match b {
+                    Block::Heading(h) => Some(h.plain_text()),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        // without front matter, its closing line makes the line above it a heading
+        let md = parse(Dialect::Original);
+        assert!(md.front_matter().is_none());
+        assert_eq!(headings(&md), ["--- title: Notes", "Notes"]);
+
+        let md = parse(Dialect::CommonMark);
+        assert_eq!(headings(&md), ["--- title: Notes"]);
+        assert!(md.inlines().all(|t| !matches!(t, MarkdownText::AutoLink(_))));
+
+        #[cfg(feature = "gfm")]
+        {
+            let md = parse(Dialect::Gfm);
+            assert!(md.front_matter().is_some());
+            assert_eq!(
+                md.to_html(),
+                "\n<p>#Notes</p>\n<p>See <a href=\"https://x.y/z\">https://x.y/z</a>.\n</p>"
+            );
+            assert_eq!(md.to_markdown(), input);
+        }
+    }
+}
@@ -1,24 +1,131 @@
-use clap::{Parser, ValueEnum};
+use std::io::IsTerminal;
+
+use clap::{Parser, Subcommand, ValueEnum};
 use color_eyre::eyre::{eyre, Context, Result};
-use md2md::{AsHtml, AsText, Markdown};
+use md2md::{
+    ansi::AnsiOptions,
+    ast::Document,
+    format::{FormatOptions, HeadingStyle, LinkStyle, Wrap},
+    html::Standalone,
+    latex::{LatexOptions, Sectioning},
+    parser::Dialect,
+    plain::PlainTextOptions,
+    render::{MarkdownRenderer, RenderOptions, SoftBreak},
+    roff::RoffOptions,
+    toc::TocOptions,
+    AsText, HtmlOptions, Markdown,
+};
+
+mod config;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Where to consume input from
-    #[arg(short, long)]
-    input: std::path::PathBuf,
+    #[arg(short, long, required = true)]
+    input: Option<std::path::PathBuf>,
 
     /// Where to write output to
+    #[arg(short, long, required_unless_present = "print_config")]
+    output: Option<std::path::PathBuf>,
+
+    /// Read the input as `md2md`, `original` Markdown, `commonmark` or `gfm`.  Overrides the
+    /// configured value.
+    #[arg(long)]
+    dialect: Option<Dialect>,
+
+    /// Default tab width for converting tabs to spaces.  Overrides the configured value, which
+    /// defaults to 4.
+    #[arg(short = 'w', long)]
+    tab_width: Option<usize>,
+
+    /// Rewrite H1 and H2 headings in this style: `preserve`, `atx` or `setext`.  Overrides the
+    /// configured value.
+    #[arg(long)]
+    heading_style: Option<HeadingStyle>,
+
+    /// Reflow paragraphs to this many columns, `preserve` existing line breaks, `never` wrap, or
+    /// put each `sentence` on its own line.  Overrides the configured value.
+    #[arg(long)]
+    wrap: Option<Wrap>,
+
+    /// Rewrite links and images as `inline` or `reference` links, or `preserve` them.  Overrides
+    /// the configured value.
+    #[arg(long)]
+    link_style: Option<LinkStyle>,
+
+    /// When writing plain text, leave out the target of each link
+    #[arg(long)]
+    no_link_urls: bool,
+
+    /// When writing html, add a link to each heading inside the heading itself
+    #[arg(long)]
+    heading_links: bool,
+
+    /// When writing html, close empty elements the way xhtml needs, as in `<br />`
+    #[arg(long)]
+    xhtml: bool,
+
+    /// When writing anything but markdown, keep each line break inside a paragraph as a
+    /// `newline`, or join the lines with a `space`
+    #[arg(long, default_value_t)]
+    soft_break: SoftBreak,
+
+    /// When writing html, put this in front of every heading id
+    #[arg(long, default_value = "")]
+    heading_id_prefix: String,
+
+    /// When writing html, make links open in a new tab
+    #[arg(long)]
+    links_in_new_tab: bool,
+
+    /// When writing html or LaTeX, write a complete document instead of a fragment
+    #[arg(long)]
+    standalone: bool,
+
+    /// The title of a standalone html or LaTeX document.  Defaults to the first H1 in the input.
+    #[arg(long, requires = "standalone")]
+    title: Option<String>,
+
+    /// Link to this stylesheet from a standalone html document.  Can be given more than once.
+    #[arg(long = "css", requires = "standalone")]
+    stylesheets: Vec<String>,
+
+    /// Template for a standalone html document, with `{{title}}`, `{{body}}`, `{{toc}}` and
+    /// `{{stylesheets}}` placeholders
+    #[arg(long, requires = "standalone")]
+    template: Option<std::path::PathBuf>,
+
+    /// When writing LaTeX, the sectioning command used for H1 headings, e.g. `chapter`
+    #[arg(long, default_value_t)]
+    base_heading: Sectioning,
+
+    /// Use this config file instead of searching for a `.md2md.toml` next to the input
     #[arg(short, long)]
-    output: std::path::PathBuf,
+    config: Option<std::path::PathBuf>,
 
-    /// Default tab width for converting tabs to spaces.
-    #[arg(short = 'w', long, default_value_t = 4)]
-    tab_width: usize,
+    /// Print the configuration that applies to the input and exit
+    #[arg(long)]
+    print_config: bool,
 
     #[arg(value_enum, short = 't', long, default_value_t)]
     output_type: OutputType,
+
+    #[arg(value_enum, long, default_value_t)]
+    input_type: InputType,
+}
+
+#[derive(ValueEnum, Default, Debug, Clone, Copy, PartialEq, Eq)]
+enum InputType {
+    /// Read input as markdown
+    #[default]
+    Markdown,
+    /// Read input as a syntax tree, in the json format written by `--output-type json`
+    Json,
 }
 
 #[derive(ValueEnum, Default, Debug, Clone, Copy, PartialEq, Eq)]
@@ -27,6 +134,53 @@ enum OutputType {
     #[default]
     Markdown,
     Html,
+    /// Write output as plain text, without any markup
+    Text,
+    /// Write output as a man page
+    Man,
+    /// Write output as LaTeX
+    Latex,
+    /// Write the syntax tree as json
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Show a markdown file in the terminal
+    View {
+        /// The file to show
+        file: std::path::PathBuf,
+
+        /// Wrap text to this many columns.  Defaults to the width of the terminal.
+        #[arg(short, long)]
+        width: Option<usize>,
+
+        /// When to use colours, text styles and hyperlinks
+        #[arg(value_enum, long, default_value_t)]
+        color: ColorChoice,
+
+        /// Use this config file instead of searching for a `.md2md.toml` next to the file
+        #[arg(short, long)]
+        config: Option<std::path::PathBuf>,
+    },
+    /// Convert an html document or fragment to markdown
+    FromHtml {
+        /// The html file to convert
+        file: std::path::PathBuf,
+
+        /// Where to write the markdown to.  Defaults to standard output.
+        #[arg(short, long)]
+        output: Option<std::path::PathBuf>,
+    },
+}
+
+#[derive(ValueEnum, Default, Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorChoice {
+    /// Use colours when writing to a terminal
+    #[default]
+    Auto,
+    Always,
+    Never,
 }
 
 fn main() -> Result<()> {
@@ -34,27 +188,203 @@ fn main() -> Result<()> {
 
     let args = Args::parse();
 
-    let input = std::fs::read_to_string(&args.input)
-        .with_context(|| eyre!("Error reading `{:?}`", &args.input))?;
+    match args.command {
+        Some(Command::View {
+            file,
+            width,
+            color,
+            config,
+        }) => return view(&file, width, color, config.as_deref()),
+        Some(Command::FromHtml { file, output }) => return from_html(&file, output.as_deref()),
+        None => {}
+    }
+    let Some(input_path) = args.input else {
+        unreachable!("clap requires an input unless running a subcommand")
+    };
+
+    let mut config = config::Config::resolve(&input_path, args.config.as_deref())?;
+    if let Some(dialect) = args.dialect {
+        config.dialect = dialect;
+    }
+    if let Some(tab_width) = args.tab_width {
+        config.tab_width = tab_width;
+    }
+    if let Some(heading_style) = args.heading_style {
+        config.heading_style = heading_style;
+    }
+    if let Some(wrap) = args.wrap {
+        config.wrap = wrap;
+    }
+    if let Some(link_style) = args.link_style {
+        config.link_style = link_style;
+    }
+
+    if args.print_config {
+        print!("{}", toml::to_string(&config)?);
+        return Ok(());
+    }
+    let Some(output_path) = args.output else {
+        unreachable!("clap requires an output unless printing the config")
+    };
+
+    let input = std::fs::read_to_string(&input_path)
+        .with_context(|| eyre!("Error reading `{:?}`", &input_path))?;
 
+    let template = match &args.template {
+        Some(path) => Some(
+            std::fs::read_to_string(path)
+                .with_context(|| eyre!("Error reading template `{:?}`", path))?,
+        ),
+        None => None,
+    };
+
+    let cleaned_input = md2md::cleanup(&input, config.tab_width);
+    let document: Option<Document> = match args.input_type {
+        InputType::Markdown => None,
+        InputType::Json => Some(
+            serde_json::from_str(&input).with_context(|| eyre!("Error parsing the syntax tree"))?,
+        ),
+    };
+    let mut md = match &document {
+        Some(document) => Markdown::from(document),
+        None => Markdown::parse_cleaned_with(&cleaned_input, &input, &config.parse_options()),
+    };
+    report_warnings(&md);
+    let toc_options = TocOptions {
+        min_level: config.toc_min_level,
+        max_level: config.toc_max_level,
+    };
+    md.format(&FormatOptions {
+        heading_style: config.heading_style,
+        list_marker: config.list_marker,
+        emphasis_style: config.emphasis_style,
+        wrap: config.wrap,
+        abbreviations: config.abbreviations.clone(),
+        link_style: config.link_style,
+        reference_labels: config.reference_labels,
+        reference_placement: config.reference_placement,
+        toc: toc_options,
+    });
+
+    let render_options = RenderOptions {
+        xhtml: args.xhtml,
+        soft_break: args.soft_break,
+        heading_id_prefix: args.heading_id_prefix,
+        links_in_new_tab: args.links_in_new_tab,
+    };
+
+    // only open the output once everything has been read, since it may well be the input
     let mut output = std::fs::OpenOptions::new()
         .write(true)
         .truncate(true)
         .create(true)
-        .open(&args.output)
-        .with_context(|| eyre!("Failed to open `{:?}` for writing", &args.output))?;
-
-    let cleaned_input = md2md::cleanup(&input, args.tab_width);
-    let md = Markdown::parse(&cleaned_input).with_context(|| eyre!("Error parsing markdown"))?;
+        .open(&output_path)
+        .with_context(|| eyre!("Failed to open `{:?}` for writing", &output_path))?;
 
     match args.output_type {
         OutputType::Markdown => md
-            .write_as_text(&mut output)
-            .with_context(|| eyre!("Failed to write markdown to `{:?}`", &args.output))?,
+            .render(&MarkdownRenderer, &mut output, &render_options)
+            .with_context(|| eyre!("Failed to write markdown to `{:?}`", &output_path))?,
+        OutputType::Json => serde_json::to_writer_pretty(&mut output, &md.to_ast())
+            .with_context(|| eyre!("Failed to write json to `{:?}`", &output_path))?,
+        OutputType::Latex => md
+            .render(
+                &LatexOptions {
+                    base_level: args.base_heading,
+                    standalone: args.standalone,
+                    title: args.title.clone(),
+                },
+                &mut output,
+                &render_options,
+            )
+            .with_context(|| eyre!("Failed to write LaTeX to `{:?}`", &output_path))?,
         OutputType::Html => md
-            .write_html(&mut output)
-            .with_context(|| eyre!("Failed to write html to `{:?}`", &args.output))?,
+            .render(
+                &HtmlOptions {
+                    heading_self_links: args.heading_links,
+                    standalone: args.standalone.then_some(Standalone {
+                        title: args.title,
+                        stylesheets: args.stylesheets,
+                        template,
+                        toc: toc_options,
+                    }),
+                },
+                &mut output,
+                &render_options,
+            )
+            .with_context(|| eyre!("Failed to write html to `{:?}`", &output_path))?,
+        OutputType::Text => md
+            .render(
+                &PlainTextOptions {
+                    link_urls: !args.no_link_urls,
+                },
+                &mut output,
+                &render_options,
+            )
+            .with_context(|| eyre!("Failed to write text to `{:?}`", &output_path))?,
+        OutputType::Man => md
+            .render(&RoffOptions::default(), &mut output, &render_options)
+            .with_context(|| eyre!("Failed to write a man page to `{:?}`", &output_path))?,
     }
 
     Ok(())
 }
+
+/// Write `file` to the terminal, styled and wrapped to fit.
+fn view(
+    file: &std::path::Path,
+    width: Option<usize>,
+    color: ColorChoice,
+    config: Option<&std::path::Path>,
+) -> Result<()> {
+    let config = config::Config::resolve(file, config)?;
+    let input =
+        std::fs::read_to_string(file).with_context(|| eyre!("Error reading `{:?}`", file))?;
+    let cleaned_input = md2md::cleanup(&input, config.tab_width);
+    let md = Markdown::parse_cleaned_with(&cleaned_input, &input, &config.parse_options());
+    report_warnings(&md);
+
+    let stdout = std::io::stdout();
+    let is_terminal = stdout.is_terminal();
+    let width = width.or_else(|| {
+        terminal_size::terminal_size().map(|(terminal_size::Width(width), _)| width as usize)
+    });
+    let options = AnsiOptions {
+        width: width.or(Some(80)),
+        color: match color {
+            ColorChoice::Auto => is_terminal,
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        },
+    };
+
+    md.write_ansi(&mut stdout.lock(), &options)
+        .with_context(|| eyre!("Failed to write to the terminal"))
+}
+
+/// Convert the html in `file` to markdown.
+fn from_html(file: &std::path::Path, output_path: Option<&std::path::Path>) -> Result<()> {
+    let input =
+        std::fs::read_to_string(file).with_context(|| eyre!("Error reading `{:?}`", file))?;
+    let document = md2md::from_html::parse_html(&input);
+    let md = Markdown::from(&document);
+
+    match output_path {
+        Some(path) => {
+            let mut output = std::fs::File::create(path)
+                .with_context(|| eyre!("Failed to open `{:?}` for writing", path))?;
+            md.write_as_text(&mut output)
+                .with_context(|| eyre!("Failed to write markdown to `{:?}`", path))
+        }
+        None => md
+            .write_as_text(&mut std::io::stdout().lock())
+            .with_context(|| eyre!("Failed to write markdown")),
+    }
+}
+
+/// Tell the user about anything questionable that was found while parsing `md`.
+fn report_warnings(md: &Markdown) {
+    for warning in md.warnings() {
+        eprintln!("warning: {warning}");
+    }
+}
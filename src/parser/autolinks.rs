@@ -0,0 +1,171 @@
+//! Bare urls, which GFM turns into links without needing angle brackets around them.
+
+use std::borrow::Cow;
+
+use crate::span::Span;
+
+use super::{block::Block, links::AutoLink, util::MarkdownText};
+
+/// Turn every bare url in the paragraphs and headings of `blocks` into an [`AutoLink`].  The text
+/// of links is left alone.
+///
+/// This runs before the blocks are located, while spans still point into the source.
+pub(crate) fn link_literals(blocks: &mut [Block<'_>]) {
+    for block in blocks.iter_mut() {
+        match block {
+            Block::Paragraph(p) => link_text(p.text_mut()),
+            Block::Heading(h) => link_text(h.text_mut()),
+            _ => {}
+        }
+    }
+}
+
+fn link_text(text: &mut Vec<MarkdownText<'_>>) {
+    let mut linked = Vec::with_capacity(text.len());
+    // the character before the current node, or `None` at the start of the text
+    let mut previous = None;
+    for t in text.drain(..) {
+        let next = match &t {
+            MarkdownText::Text(s, _) => s.chars().last().or(previous),
+            MarkdownText::SoftBreak(_) => Some('\n'),
+            _ => Some('>'),
+        };
+        match t {
+            MarkdownText::Text(Cow::Borrowed(s), span) => {
+                split_text(s, span, previous, &mut linked)
+            }
+            t => linked.push(t),
+        }
+        previous = next;
+    }
+    *text = linked;
+}
+
+/// Push `s` to `output`, split into text and the bare urls in it.
+fn split_text<'a>(
+    s: &'a str,
+    span: Span,
+    previous: Option<char>,
+    output: &mut Vec<MarkdownText<'a>>,
+) {
+    let mut start = 0;
+    let mut before = previous;
+    for (i, c) in s.char_indices() {
+        let can_start = before.is_none_or(|b| b.is_whitespace() || "*_~(".contains(b));
+        before = Some(c);
+        if i < start || !can_start {
+            continue;
+        }
+        let Some(url) = literal(&s[i..]) else {
+            continue;
+        };
+
+        if start < i {
+            output.push(MarkdownText::Text(
+                Cow::Borrowed(&s[start..i]),
+                span.slice(s, &s[start..i]),
+            ));
+        }
+        output.push(MarkdownText::AutoLink(AutoLink {
+            target: Cow::Borrowed(url),
+            literal: true,
+            span: span.slice(s, url),
+        }));
+        start = i + url.len();
+    }
+
+    if start == 0 {
+        output.push(MarkdownText::Text(Cow::Borrowed(s), span));
+    } else if start < s.len() {
+        output.push(MarkdownText::Text(
+            Cow::Borrowed(&s[start..]),
+            span.slice(s, &s[start..]),
+        ));
+    }
+}
+
+/// The bare url at the start of `input`, if there is one.
+fn literal(input: &str) -> Option<&str> {
+    let rest = ["https://", "http://", "www."]
+        .iter()
+        .find_map(|scheme| input.strip_prefix(scheme))?;
+
+    let end = input
+        .find(|c: char| c.is_whitespace() || c == '<')
+        .unwrap_or(input.len());
+    let mut url = &input[..end];
+
+    // punctuation at the end is taken to be part of the sentence the url is in
+    loop {
+        let trimmed = url.trim_end_matches(['?', '!', '.', ',', ':', '*', '_', '~']);
+        let trimmed = match trimmed.strip_suffix(')') {
+            Some(t) if t.matches('(').count() < t.matches(')').count() + 1 => t,
+            _ => trimmed,
+        };
+        if trimmed == url {
+            break;
+        }
+        url = trimmed;
+    }
+
+    let address = url.get(input.len() - rest.len()..)?;
+    let domain = &address[..address.find(['/', '?', '#']).unwrap_or(address.len())];
+    let valid = !domain.is_empty()
+        && domain
+            .chars()
+            .all(|c| c.is_alphanumeric() || "-_.:".contains(c));
+    valid.then_some(url)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn links(input: &str) -> Vec<&str> {
+        let mut text = vec![MarkdownText::text(input)];
+        link_text(&mut text);
+        text.into_iter()
+            .filter_map(|t| match t {
+                MarkdownText::AutoLink(AutoLink {
+                    target: Cow::Borrowed(target),
+                    ..
+                }) => Some(target),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn bare_urls() {
+        assert_eq!(
+            links("see https://x.y/a?b=c, or www.z.com."),
+            ["https://x.y/a?b=c", "www.z.com"]
+        );
+        assert_eq!(links("(at http://x.y/f(1))"), ["http://x.y/f(1)"]);
+        assert_eq!(links("*https://x.y*"), ["https://x.y"]);
+    }
+
+    #[test]
+    fn not_urls() {
+        assert!(links("nohttps://x.y").is_empty());
+        assert!(links("https:// or www.").is_empty());
+    }
+
+    #[test]
+    fn spans() {
+        let input = "go to www.x.y now";
+        let mut text = vec![MarkdownText::text(input)];
+        link_text(&mut text);
+        let spans: Vec<_> = text
+            .iter_mut()
+            .map(|t| {
+                crate::span::Locate::locate(t, input);
+                t.span()
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            [Span::new(0, 6), Span::new(6, 13), Span::new(13, 17)]
+        );
+    }
+}
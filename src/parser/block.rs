@@ -1,41 +1,163 @@
-use winnow::{branch::alt, character::newline, combinator::eof, multi::{many1, count}, IResult, Parser};
+use std::{borrow::Cow, collections::HashMap};
 
-use crate::{AsHtml, AsText};
+use winnow::{
+    branch::alt,
+    character::newline,
+    combinator::{cond, eof, peek},
+    multi::{count, many1},
+    sequence::preceded,
+    Parser,
+};
+
+use crate::{
+    latex::Sectioning,
+    render::RenderOptions,
+    span::{Locate, Span},
+    toc::Toc,
+    AsHtml, AsLatex, AsText,
+};
 
 use super::{
-    headers::{parse_header, setext_header, Header},
+    comments::parse_comment,
+    headers::{atx_opening, parse_header, setext_header, Header},
+    links::{parse_link_definition, LinkDefinition},
     paragraphs::{parse_paragraph, take_until_match, Paragraph},
+    util::MarkdownText,
+    IResult, ParseOptions,
 };
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum Block<'source> {
     Paragraph(Paragraph<'source>),
     Heading(Header<'source>),
-    Separator(usize),
+    LinkDefinition(LinkDefinition<'source>),
+    /// An html comment on its own, e.g. `<!-- toc -->`
+    Comment(Cow<'source, str>, Span),
+    /// A generated table of contents
+    Toc(Toc),
+    Separator(usize, Span),
 }
 
-impl<'source> AsHtml for Block<'source> {
-    fn write_html<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+impl<'source> Block<'source> {
+    /// The inline content of a paragraph or heading.  Other blocks don't have any.
+    pub fn text(&self) -> &[MarkdownText<'source>] {
+        match self {
+            Block::Paragraph(p) => p.text(),
+            Block::Heading(h) => h.text(),
+            _ => &[],
+        }
+    }
+
+    /// The part of the source this was parsed from.  A table of contents is never parsed, so its
+    /// span is always empty.
+    pub fn span(&self) -> Span {
+        match self {
+            Block::Paragraph(p) => p.span(),
+            Block::Heading(h) => h.span(),
+            Block::LinkDefinition(d) => d.span(),
+            Block::Comment(_, span) | Block::Separator(_, span) => *span,
+            Block::Toc(_) => Span::default(),
+        }
+    }
+
+    /// Copy this block so it no longer borrows from the source.
+    pub fn into_owned(self) -> Block<'static> {
+        match self {
+            Block::Paragraph(p) => Block::Paragraph(p.into_owned()),
+            Block::Heading(h) => Block::Heading(h.into_owned()),
+            Block::LinkDefinition(d) => Block::LinkDefinition(d.into_owned()),
+            Block::Comment(c, span) => Block::Comment(Cow::Owned(c.into_owned()), span),
+            Block::Toc(toc) => Block::Toc(toc),
+            Block::Separator(amount, span) => Block::Separator(amount, span),
+        }
+    }
+}
+
+impl<'source> Locate for Block<'source> {
+    fn locate(&mut self, source: &str) {
+        match self {
+            Block::Paragraph(p) => p.locate(source),
+            Block::Heading(h) => h.locate(source),
+            Block::LinkDefinition(d) => d.locate(source),
+            Block::Comment(_, span) | Block::Separator(_, span) => span.locate(source),
+            Block::Toc(_) => {}
+        }
+    }
+}
+
+impl<'source> Block<'source> {
+    /// Write this block as html, looking up reference-style links in `definitions`.
+    pub(crate) fn render_html<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        definitions: &HashMap<String, &str>,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
         match self {
             Block::Paragraph(p) => {
                 write!(output, "<p>")?;
-                p.write_html(output)?;
+                p.render_html(output, definitions, options)?;
                 write!(output, "</p>")?;
             }
-            Block::Heading(h) => h.write_html(output)?,
-            Block::Separator(_) => writeln!(output)?,
+            Block::Heading(h) => h.render_html(output, None, false, definitions, options)?,
+            Block::LinkDefinition(d) => d.write_html(output)?,
+            Block::Comment(c, _) => write!(output, "{c}")?,
+            Block::Toc(toc) => toc.render_html(output, options)?,
+            Block::Separator(..) => writeln!(output)?,
         }
 
         Ok(())
     }
 }
 
+impl<'source> AsHtml for Block<'source> {
+    fn write_html<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+        self.render_html(output, &HashMap::new(), &RenderOptions::default())
+    }
+}
+
+impl<'source> Block<'source> {
+    /// Write this block as LaTeX, looking up reference-style links in `definitions`.
+    pub(crate) fn render_latex<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        definitions: &HashMap<String, &str>,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        match self {
+            Block::Paragraph(p) => p.render_latex(output, definitions, options)?,
+            Block::Heading(h) => {
+                h.render_latex(output, Sectioning::default(), definitions, options)?
+            }
+            Block::LinkDefinition(d) => d.write_latex(output)?,
+            Block::Comment(..) => {}
+            Block::Toc(toc) => toc.write_latex(output)?,
+            Block::Separator(amount, _) => {
+                for _ in 0..*amount {
+                    writeln!(output)?
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<'source> AsLatex for Block<'source> {
+    fn write_latex<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+        self.render_latex(output, &HashMap::new(), &RenderOptions::default())
+    }
+}
+
 impl<'source> AsText for Block<'source> {
     fn write_as_text<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
         match self {
             Block::Paragraph(p) => p.write_as_text(output)?,
             Block::Heading(h) => h.write_as_text(output)?,
-            Block::Separator(amount) => {
+            Block::LinkDefinition(d) => d.write_as_text(output)?,
+            Block::Comment(c, _) => write!(output, "{c}")?,
+            Block::Toc(toc) => toc.write_as_text(output)?,
+            Block::Separator(amount, _) => {
                 for _ in 0..*amount {
                     writeln!(output)?
                 }
@@ -45,17 +167,34 @@ impl<'source> AsText for Block<'source> {
     }
 }
 
-pub fn parse_block(input: &str) -> IResult<&str, Block> {
-    alt((
-        many1(newline).map(Block::Separator),
-        take_until_match(alt((count(newline, 2), eof.void())))
-            .and_then(setext_header.map(Block::Heading)),
-        parse_header.map(Block::Heading),
-        // try parsing a paragraph last, since we should try to recognize other block types first
-        parse_paragraph.map(Block::Paragraph),
-    ))
-    .context("block")
-    .parse_next(input)
+impl<'source> std::fmt::Display for Block<'source> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_as_text_fmt(f)
+    }
+}
+
+pub fn parse_block<'a>(
+    options: &ParseOptions,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Block<'a>> {
+    let needs_space = !options.headings_without_space;
+    let mut paragraph = parse_paragraph(options);
+    move |input: &'a str| {
+        alt((
+            many1(newline)
+                .with_recognized()
+                .map(|(newlines, s)| Block::Separator(newlines, Span::of(s))),
+            parse_comment.map(|c| Block::Comment(c.into(), Span::of(c))),
+            take_until_match(alt((count(newline, 2), eof.void())))
+                .and_then(setext_header.map(Block::Heading)),
+            preceded(cond(needs_space, peek(atx_opening)), parse_header).map(Block::Heading),
+            parse_link_definition.map(Block::LinkDefinition),
+            // try parsing a paragraph last, since we should try to recognize other block types
+            // first
+            (&mut paragraph).map(Block::Paragraph),
+        ))
+        .context("block")
+        .parse_next(input)
+    }
 }
 
 #[cfg(test)]
@@ -69,57 +208,75 @@ mod test {
 
     use super::*;
 
+    fn parse_one(input: &str) -> Block<'_> {
+        let mut block = parse_block(&ParseOptions::default())
+            .parse_next(input)
+            .finish()
+            .unwrap();
+        block.locate(input);
+        block
+    }
+
+    fn parse_all(input: &str) -> Vec<Block<'_>> {
+        let mut blocks: Vec<_> = many1(parse_block(&ParseOptions::default()))
+            .parse_next(input)
+            .finish()
+            .unwrap();
+        blocks.locate(input);
+        blocks
+    }
+
     #[test]
     fn parse_paragraph() {
-        let input = "just a paragraph";
-        let block = parse_block(input).finish().unwrap();
         assert_eq!(
-            block,
+            parse_one("just a paragraph"),
             Block::Paragraph(Paragraph {
-                text: vec![Text("just a paragraph")]
+                text: vec![Text("just a paragraph".into(), Span::new(0, 16))],
+                span: Span::new(0, 16),
             })
         )
     }
 
     #[test]
     fn parse_paragraph_with_trailing_newline() {
-        let input = "just a paragraph\n";
-        let block = parse_block(input).finish().unwrap();
         assert_eq!(
-            block,
+            parse_one("just a paragraph\n"),
             Block::Paragraph(Paragraph {
-                text: vec![Text("just a paragraph"), SoftBreak]
+                text: vec![
+                    Text("just a paragraph".into(), Span::new(0, 16)),
+                    SoftBreak(Span::new(16, 17))
+                ],
+                span: Span::new(0, 17),
             })
         )
     }
 
     #[test]
     fn parse_header() {
-        let input = "# header";
-        let block = parse_block(input).finish().unwrap();
         assert_eq!(
-            block,
+            parse_one("# header"),
             Block::Heading(Header::AtxHeader {
                 level: HeadingLevel::H1,
-                text: vec![Text("header")],
+                text: vec![Text("header".into(), Span::new(2, 8))],
+                span: Span::new(0, 8),
             })
         )
     }
 
     #[test]
     fn block_stream() {
-        let input = "# header\nthis is some text";
-        let blocks: Vec<_> = many1(parse_block).parse_next(input).finish().unwrap();
         assert_eq!(
-            blocks,
+            parse_all("# header\nthis is some text"),
             [
                 Block::Heading(Header::AtxHeader {
                     level: HeadingLevel::H1,
-                    text: vec![Text("header")]
+                    text: vec![Text("header".into(), Span::new(2, 8))],
+                    span: Span::new(0, 8),
                 }),
-                Block::Separator(1),
+                Block::Separator(1, Span::new(8, 9)),
                 Block::Paragraph(Paragraph {
-                    text: vec![Text("this is some text")]
+                    text: vec![Text("this is some text".into(), Span::new(9, 26))],
+                    span: Span::new(9, 26),
                 })
             ]
         )
@@ -127,29 +284,32 @@ mod test {
 
     #[test]
     fn block_neighboring_lines() {
-        let input = "foo\nbar";
-        let blocks: Vec<_> = many1(parse_block).parse_next(input).finish().unwrap();
         assert_eq!(
-            blocks,
+            parse_all("foo\nbar"),
             [Block::Paragraph(Paragraph {
-                text: vec![Text("foo"), SoftBreak, Text("bar")]
+                text: vec![
+                    Text("foo".into(), Span::new(0, 3)),
+                    SoftBreak(Span::new(3, 4)),
+                    Text("bar".into(), Span::new(4, 7))
+                ],
+                span: Span::new(0, 7),
             })]
         )
     }
 
     #[test]
     fn block_separate_paragraphs() {
-        let input = "foo\n\nbar";
-        let blocks: Vec<_> = many1(parse_block).parse_next(input).finish().unwrap();
         assert_eq!(
-            blocks,
+            parse_all("foo\n\nbar"),
             [
                 Block::Paragraph(Paragraph {
-                    text: vec![Text("foo")]
+                    text: vec![Text("foo".into(), Span::new(0, 3))],
+                    span: Span::new(0, 3),
                 }),
-                Block::Separator(2),
+                Block::Separator(2, Span::new(3, 5)),
                 Block::Paragraph(Paragraph {
-                    text: vec![Text("bar")]
+                    text: vec![Text("bar".into(), Span::new(5, 8))],
+                    span: Span::new(5, 8),
                 })
             ]
         )
@@ -157,38 +317,82 @@ mod test {
 
     #[test]
     fn trailing_header() {
-        let input = "foo\n# bar";
-        let blocks: Vec<_> = many1(parse_block).parse_next(input).finish().unwrap();
         assert_eq!(
-            blocks,
+            parse_all("foo\n# bar"),
             [
                 Block::Paragraph(Paragraph {
-                    text: vec![Text("foo")]
+                    text: vec![Text("foo".into(), Span::new(0, 3))],
+                    span: Span::new(0, 3),
                 }),
-                Block::Separator(1),
+                Block::Separator(1, Span::new(3, 4)),
                 Block::Heading(Header::AtxHeader {
                     level: HeadingLevel::H1,
-                    text: vec![Text("bar")]
+                    text: vec![Text("bar".into(), Span::new(6, 9))],
+                    span: Span::new(4, 9),
+                }),
+            ]
+        )
+    }
+
+    #[test]
+    fn link_definitions() {
+        assert_eq!(
+            parse_all("text\n\n[foo]: https://example.com\n[bar]: /bar"),
+            [
+                Block::Paragraph(Paragraph {
+                    text: vec![Text("text".into(), Span::new(0, 4))],
+                    span: Span::new(0, 4),
+                }),
+                Block::Separator(2, Span::new(4, 6)),
+                Block::LinkDefinition(LinkDefinition {
+                    label: "foo".into(),
+                    target: "https://example.com".into(),
+                    title: None,
+                    span: Span::new(6, 32),
+                }),
+                Block::Separator(1, Span::new(32, 33)),
+                Block::LinkDefinition(LinkDefinition {
+                    label: "bar".into(),
+                    target: "/bar".into(),
+                    title: None,
+                    span: Span::new(33, 44),
+                }),
+            ]
+        )
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(
+            parse_all("text\n<!-- toc -->\n<!-- tocstop -->"),
+            [
+                Block::Paragraph(Paragraph {
+                    text: vec![Text("text".into(), Span::new(0, 4))],
+                    span: Span::new(0, 4),
                 }),
+                Block::Separator(1, Span::new(4, 5)),
+                Block::Comment("<!-- toc -->".into(), Span::new(5, 17)),
+                Block::Separator(1, Span::new(17, 18)),
+                Block::Comment("<!-- tocstop -->".into(), Span::new(18, 34)),
             ]
         )
     }
 
     #[test]
     fn bad_header() {
-        let input = "test\n\nfoo\n---";
-        let blocks: Vec<_> = many1(parse_block).parse_next(input).finish().unwrap();
         assert_eq!(
-            blocks,
+            parse_all("test\n\nfoo\n---"),
             [
                 Block::Paragraph(Paragraph {
-                    text: vec![Text("test")]
+                    text: vec![Text("test".into(), Span::new(0, 4))],
+                    span: Span::new(0, 4),
                 }),
-                Block::Separator(2),
+                Block::Separator(2, Span::new(4, 6)),
                 Block::Heading(Header::SetextHeader {
                     level: HeadingLevel::H2,
                     level_len: 3,
-                    text: vec![Text("foo")]
+                    text: vec![Text("foo".into(), Span::new(6, 9))],
+                    span: Span::new(6, 13),
                 }),
             ]
         )
@@ -1,11 +1,17 @@
-use winnow::{bytes::take_until1, sequence::delimited, IResult, Parser};
+use winnow::{bytes::take_until1, sequence::delimited, Parser};
 
-use super::util::MarkdownText;
+use crate::span::Span;
 
-pub fn parse_inline_code(input: &str) -> IResult<&str, MarkdownText> {
+use super::{util::MarkdownText, IResult};
+
+pub fn parse_inline_code(input: &str) -> IResult<&str, MarkdownText<'_>> {
     delimited("`", take_until1("`"), "`")
         .context("parse_inline_code")
-        .map(|s: &str| MarkdownText::Code { code: s.into() })
+        .with_recognized()
+        .map(|(code, s): (&str, _)| MarkdownText::Code {
+            code: code.into(),
+            span: Span::of(s),
+        })
         .parse_next(input)
 }
 
@@ -14,15 +20,18 @@ mod test {
     use winnow::FinishIResult;
 
     use super::*;
+    use crate::span::Locate;
 
     #[test]
     fn inline_code() {
         let text = "`abxy`";
-        let code = parse_inline_code(text).finish().unwrap();
+        let mut code = parse_inline_code(text).finish().unwrap();
+        code.locate(text);
         assert_eq!(
             code,
             MarkdownText::Code {
-                code: "abxy".into()
+                code: "abxy".into(),
+                span: Span::new(0, 6),
             }
         )
     }
@@ -36,11 +45,13 @@ mod test {
     #[test]
     fn across_lines() {
         let text = "`inline\ncode\nhere`";
-        let code = parse_inline_code(text).finish().unwrap();
+        let mut code = parse_inline_code(text).finish().unwrap();
+        code.locate(text);
         assert_eq!(
             code,
             MarkdownText::Code {
-                code: "inline\ncode\nhere".into()
+                code: "inline\ncode\nhere".into(),
+                span: Span::new(0, 18),
             }
         )
     }
@@ -0,0 +1,56 @@
+use winnow::{
+    branch::alt,
+    bytes::take_until0,
+    character::{newline, space0},
+    combinator::{eof, peek},
+    sequence::terminated,
+    Parser,
+};
+
+use super::IResult;
+
+/// Parse an html comment that takes up a whole block, like `<!-- toc -->`.  The comment is kept
+/// exactly as it was written, including the `<!--` and `-->`.
+pub fn parse_comment(input: &str) -> IResult<&str, &str> {
+    terminated(
+        ("<!--", take_until0("-->"), "-->").recognize(),
+        (space0, peek(alt((newline.void(), eof.void())))),
+    )
+    .context("html comment")
+    .parse_next(input)
+}
+
+/// The text inside of a comment, without the `<!--` and `-->`.
+pub fn comment_text(comment: &str) -> &str {
+    comment
+        .trim_start_matches("<!--")
+        .trim_end_matches("-->")
+        .trim()
+}
+
+#[cfg(test)]
+mod test {
+    use winnow::FinishIResult;
+
+    use super::*;
+
+    #[test]
+    fn comment() {
+        let (remaining, comment) = parse_comment("<!-- toc -->\nfoo").unwrap();
+        assert_eq!(remaining, "\nfoo");
+        assert_eq!(comment, "<!-- toc -->");
+        assert_eq!(comment_text(comment), "toc");
+    }
+
+    #[test]
+    fn multiline_comment() {
+        let comment = parse_comment("<!--\nsome\nnotes\n-->  ").finish().unwrap();
+        assert_eq!(comment_text(comment), "some\nnotes");
+    }
+
+    #[test]
+    fn inline_comment() {
+        assert!(parse_comment("<!-- toc --> and more").is_err());
+        assert!(parse_comment("<!-- unterminated").is_err());
+    }
+}
@@ -0,0 +1,262 @@
+use std::{borrow::Cow, collections::BTreeMap};
+
+use winnow::combinator::fail;
+
+use super::IResult;
+
+/// The language front matter is written in, which is decided by its delimiters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrontMatterKind {
+    /// Delimited by `---`
+    Yaml,
+    /// Delimited by `+++`
+    Toml,
+}
+
+impl FrontMatterKind {
+    fn delimiter(self) -> &'static str {
+        match self {
+            FrontMatterKind::Yaml => "---",
+            FrontMatterKind::Toml => "+++",
+        }
+    }
+}
+
+/// Metadata at the very start of a document, as used by static site generators.  The front
+/// matter is kept exactly as it was written, delimiters included, so it can be written back out
+/// unchanged.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrontMatter<'source> {
+    pub kind: FrontMatterKind,
+    /// The front matter as it was written, including the delimiters
+    pub raw: Cow<'source, str>,
+    /// The top-level keys of the front matter, and their values as text.  Lists are joined with
+    /// `, `; nested tables are left out.  If the front matter can't be parsed, this is empty.
+    pub metadata: BTreeMap<String, String>,
+}
+
+impl<'source> FrontMatter<'source> {
+    /// Copy the front matter so it no longer borrows from the source.
+    pub fn into_owned(self) -> FrontMatter<'static> {
+        FrontMatter {
+            kind: self.kind,
+            raw: Cow::Owned(self.raw.into_owned()),
+            metadata: self.metadata,
+        }
+    }
+
+    /// Why the metadata couldn't be read, and where in the front matter it went wrong.
+    #[cfg(feature = "front-matter")]
+    pub(crate) fn error(&self) -> Option<(usize, String)> {
+        let (opening, rest) = self.raw.split_once('\n')?;
+        let content = rest.rsplit_once('\n').map_or("", |(content, _)| content);
+        if content.trim().is_empty() {
+            return None;
+        }
+
+        // both report where in the content they went wrong, which is turned into an offset in
+        // the document
+        let (at, error) = match self.kind {
+            FrontMatterKind::Yaml => {
+                let e = serde_yaml::from_str::<serde_yaml::Mapping>(content).err()?;
+                let mut message = e.to_string();
+                if let Some(location) = e.location() {
+                    let (line, column) = (location.line(), location.column());
+                    message = message.replacen(&format!(" at line {line} column {column}"), "", 1);
+                }
+                let at = e.location().map_or(0, |location| location.index());
+                (at, format!("couldn't read the yaml front matter: {message}"))
+            }
+            FrontMatterKind::Toml => {
+                let e = content.parse::<toml::Table>().err()?;
+                let at = e.span().map_or(0, |span| span.start);
+                let message = e.message().lines().collect::<Vec<_>>().join(", ");
+                match message.as_str() {
+                    "" => (at, "couldn't read the toml front matter".to_string()),
+                    _ => (at, format!("couldn't read the toml front matter: {message}")),
+                }
+            }
+        };
+        Some((opening.len() + 1 + at.min(content.len()), error))
+    }
+
+    /// Without the `front-matter` feature the metadata isn't read, so it can't go wrong.
+    #[cfg(not(feature = "front-matter"))]
+    pub(crate) fn error(&self) -> Option<(usize, String)> {
+        None
+    }
+}
+
+/// Parse front matter.  This should only be tried at the start of a document.
+pub fn parse_front_matter(input: &str) -> IResult<&str, FrontMatter<'_>> {
+    for kind in [FrontMatterKind::Yaml, FrontMatterKind::Toml] {
+        let delimiter = kind.delimiter();
+        let mut lines = input.split_inclusive('\n');
+        let Some(opening) = lines
+            .next()
+            .filter(|line| line.trim_end() == delimiter && line.ends_with('\n'))
+        else {
+            continue;
+        };
+
+        let mut end = opening.len();
+        for line in lines {
+            let start = end;
+            end += line.len();
+            let closing = line.trim_end();
+            if closing == delimiter || (kind == FrontMatterKind::Yaml && closing == "...") {
+                let content = &input[opening.len()..start];
+                let end = start + line.trim_end_matches('\n').len();
+                return Ok((
+                    &input[end..],
+                    FrontMatter {
+                        kind,
+                        raw: input[..end].into(),
+                        metadata: parse_metadata(kind, content),
+                    },
+                ));
+            }
+        }
+    }
+
+    fail(input)
+}
+
+#[cfg(not(feature = "front-matter"))]
+fn parse_metadata(_kind: FrontMatterKind, _content: &str) -> BTreeMap<String, String> {
+    BTreeMap::new()
+}
+
+#[cfg(feature = "front-matter")]
+fn parse_metadata(kind: FrontMatterKind, content: &str) -> BTreeMap<String, String> {
+    match kind {
+        FrontMatterKind::Yaml => {
+            fn text(value: &serde_yaml::Value) -> Option<String> {
+                match value {
+                    serde_yaml::Value::Bool(b) => Some(b.to_string()),
+                    serde_yaml::Value::Number(n) => Some(n.to_string()),
+                    serde_yaml::Value::String(s) => Some(s.clone()),
+                    serde_yaml::Value::Sequence(items) => {
+                        Some(items.iter().filter_map(text).collect::<Vec<_>>().join(", "))
+                    }
+                    serde_yaml::Value::Null
+                    | serde_yaml::Value::Mapping(_)
+                    | serde_yaml::Value::Tagged(_) => None,
+                }
+            }
+
+            serde_yaml::from_str::<serde_yaml::Mapping>(content)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|(key, value)| Some((text(key)?, text(value)?)))
+                .collect()
+        }
+        FrontMatterKind::Toml => {
+            fn text(value: &toml::Value) -> Option<String> {
+                match value {
+                    toml::Value::String(s) => Some(s.clone()),
+                    toml::Value::Integer(i) => Some(i.to_string()),
+                    toml::Value::Float(f) => Some(f.to_string()),
+                    toml::Value::Boolean(b) => Some(b.to_string()),
+                    toml::Value::Datetime(d) => Some(d.to_string()),
+                    toml::Value::Array(items) => {
+                        Some(items.iter().filter_map(text).collect::<Vec<_>>().join(", "))
+                    }
+                    toml::Value::Table(_) => None,
+                }
+            }
+
+            content
+                .parse::<toml::Table>()
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|(key, value)| Some((key.clone(), text(value)?)))
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{AsText, Markdown};
+
+    #[test]
+    #[cfg(feature = "front-matter")]
+    fn yaml() {
+        let input =
+            "---\ntitle: Release notes\ntags: [a, b]\ndraft: false\nextra:\n  x: 1\n---\n\n# Hi";
+        let (remaining, front_matter) = parse_front_matter(input).unwrap();
+        assert_eq!(remaining, "\n\n# Hi");
+        assert_eq!(front_matter.kind, FrontMatterKind::Yaml);
+        assert_eq!(front_matter.raw, &input[..input.len() - 6]);
+        assert_eq!(
+            front_matter.metadata,
+            BTreeMap::from([
+                ("draft".to_string(), "false".to_string()),
+                ("tags".to_string(), "a, b".to_string()),
+                ("title".to_string(), "Release notes".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn trailing_spaces() {
+        let (remaining, front_matter) = parse_front_matter("--- \na: 1\n---  \ntext").unwrap();
+        assert_eq!(remaining, "\ntext");
+        assert_eq!(front_matter.raw, "--- \na: 1\n---  ");
+    }
+
+    #[test]
+    #[cfg(feature = "front-matter")]
+    fn toml() {
+        let (remaining, front_matter) =
+            parse_front_matter("+++\ntitle = \"Notes\"\nweight = 3\n+++").unwrap();
+        assert_eq!(remaining, "");
+        assert_eq!(front_matter.kind, FrontMatterKind::Toml);
+        assert_eq!(front_matter.metadata["title"], "Notes");
+        assert_eq!(front_matter.metadata["weight"], "3");
+    }
+
+    #[test]
+    fn not_front_matter() {
+        assert!(parse_front_matter("---\nunterminated\n").is_err());
+        assert!(parse_front_matter("---").is_err());
+        assert!(parse_front_matter("text\n---\n").is_err());
+        assert!(parse_front_matter("---\n: [\n---\n")
+            .unwrap()
+            .1
+            .metadata
+            .is_empty());
+    }
+
+    #[test]
+    fn round_trip() {
+        let input = "---\ntitle: Notes\n---\n\nSome text\n";
+        let md = Markdown::parse(input);
+        #[cfg(feature = "front-matter")]
+        assert_eq!(md.metadata("title"), Some("Notes"));
+
+        let mut output = vec![];
+        md.write_as_text(&mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), input);
+
+        let md = Markdown::parse("Some text\n\n---\ntitle: Notes\n---\n");
+        assert!(md.front_matter().is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "front-matter")]
+    fn invalid_metadata() {
+        let md = Markdown::parse("---\ntitle: [\n---\n\nSome text\n");
+        assert_eq!(md.warnings().len(), 1);
+        assert!(md.warnings()[0]
+            .message
+            .starts_with("couldn't read the yaml front matter"));
+        assert_eq!(md.warnings()[0].offset, 12);
+        assert_eq!(md.warnings()[0].context, ["front matter"]);
+
+        assert!(Markdown::parse("+++\n+++\n").warnings().is_empty());
+        assert_eq!(Markdown::parse("+++\nx =\n+++\n").warnings().len(), 1);
+    }
+}
@@ -1,19 +1,35 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
 use winnow::{
     branch::alt,
-    bytes::{any, take_till1, take_while1},
-    character::{newline, space0},
+    bytes::{any, take_till1, take_while1, take_while_m_n},
+    character::{newline, space0, space1},
     combinator::{backtrack_err, eof, fail, opt},
     dispatch,
     multi::{many0, many1},
     sequence::{delimited, preceded, terminated},
-    IResult, Parser,
+    Parser,
 };
 
-use crate::{AsHtml, AsText};
+use crate::{
+    latex::Sectioning,
+    render::RenderOptions,
+    slug::plain_text,
+    span::{Locate, Span},
+    AsHtml, AsLatex, AsText,
+};
 
-use super::{util::MarkdownText, paragraphs::take_until_match};
+use super::{
+    paragraphs::take_until_match,
+    util::{write_html_inlines, write_latex_text, MarkdownText},
+    IResult,
+};
 
-#[derive(Debug, PartialEq, Eq)]
+/// The level of a heading, from `H1` (the most important) to `H6`.  When serialized, this is the
+/// number of the level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "u8", into = "u8")]
 pub enum HeadingLevel {
     H1,
     H2,
@@ -28,57 +44,222 @@ pub enum Header<'source> {
     AtxHeader {
         level: HeadingLevel,
         text: Vec<MarkdownText<'source>>,
+        span: Span,
     },
     SetextHeader {
         level: HeadingLevel,
         level_len: usize,
         text: Vec<MarkdownText<'source>>,
+        span: Span,
     },
 }
 
-impl<'source> AsHtml for Header<'source> {
-    fn write_html<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+impl TryFrom<u8> for HeadingLevel {
+    type Error = String;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(HeadingLevel::H1),
+            2 => Ok(HeadingLevel::H2),
+            3 => Ok(HeadingLevel::H3),
+            4 => Ok(HeadingLevel::H4),
+            5 => Ok(HeadingLevel::H5),
+            6 => Ok(HeadingLevel::H6),
+            _ => Err(format!("heading levels go from 1 to 6, found {value}")),
+        }
+    }
+}
+
+impl From<HeadingLevel> for u8 {
+    fn from(level: HeadingLevel) -> Self {
+        match level {
+            HeadingLevel::H1 => 1,
+            HeadingLevel::H2 => 2,
+            HeadingLevel::H3 => 3,
+            HeadingLevel::H4 => 4,
+            HeadingLevel::H5 => 5,
+            HeadingLevel::H6 => 6,
+        }
+    }
+}
+
+impl HeadingLevel {
+    /// The html tag for a heading at this level
+    pub fn html_tag(self) -> &'static str {
         match self {
-            Header::AtxHeader { level, text } => {
-                let level_tag = match level {
-                    HeadingLevel::H1 => "h1",
-                    HeadingLevel::H2 => "h2",
-                    HeadingLevel::H3 => "h3",
-                    HeadingLevel::H4 => "h4",
-                    HeadingLevel::H5 => "h5",
-                    HeadingLevel::H6 => "h6",
-                };
-                write!(output, "<{level_tag}>")?;
-                for t in text.iter() {
-                    t.write_html(output)?;
-                }
-                write!(output, "</{level_tag}>")?;
-            }
-            Header::SetextHeader { level, text, .. } => {
-                let level_tag = match level {
-                    HeadingLevel::H1 => "h1",
-                    HeadingLevel::H2 => "h2",
-                    HeadingLevel::H3 => "h3",
-                    HeadingLevel::H4 => "h4",
-                    HeadingLevel::H5 => "h5",
-                    HeadingLevel::H6 => "h6",
-                };
-                write!(output, "<{level_tag}>")?;
-                for t in text.iter() {
-                    t.write_html(output)?;
-                }
-                write!(output, "</{level_tag}>")?;
-            }
+            HeadingLevel::H1 => "h1",
+            HeadingLevel::H2 => "h2",
+            HeadingLevel::H3 => "h3",
+            HeadingLevel::H4 => "h4",
+            HeadingLevel::H5 => "h5",
+            HeadingLevel::H6 => "h6",
+        }
+    }
+}
+
+impl<'source> Header<'source> {
+    /// The level of this heading, regardless of how it was written
+    pub fn level(&self) -> HeadingLevel {
+        match self {
+            Header::AtxHeader { level, .. } | Header::SetextHeader { level, .. } => *level,
+        }
+    }
+
+    /// The content of this heading, without its `#`s or underline
+    pub fn text(&self) -> &[MarkdownText<'source>] {
+        match self {
+            Header::AtxHeader { text, .. } | Header::SetextHeader { text, .. } => text,
+        }
+    }
+
+    pub fn text_mut(&mut self) -> &mut Vec<MarkdownText<'source>> {
+        match self {
+            Header::AtxHeader { text, .. } | Header::SetextHeader { text, .. } => text,
         }
+    }
+
+    /// The text a reader sees for this heading, without any markup
+    pub fn plain_text(&self) -> String {
+        plain_text(self.text()).trim().to_string()
+    }
+
+    /// The part of the source this was parsed from, including its `#`s or underline
+    pub fn span(&self) -> Span {
+        match self {
+            Header::AtxHeader { span, .. } | Header::SetextHeader { span, .. } => *span,
+        }
+    }
+
+    /// Copy this heading so it no longer borrows from the source.
+    pub fn into_owned(self) -> Header<'static> {
+        match self {
+            Header::AtxHeader { level, text, span } => Header::AtxHeader {
+                level,
+                text: MarkdownText::into_owned_all(text),
+                span,
+            },
+            Header::SetextHeader {
+                level,
+                level_len,
+                text,
+                span,
+            } => Header::SetextHeader {
+                level,
+                level_len,
+                text: MarkdownText::into_owned_all(text),
+                span,
+            },
+        }
+    }
+
+    fn span_mut(&mut self) -> &mut Span {
+        match self {
+            Header::AtxHeader { span, .. } | Header::SetextHeader { span, .. } => span,
+        }
+    }
+
+    /// Write this heading as html with an `id`, so that it can be linked to.  If `self_link` is
+    /// set, an anchor linking to the heading itself is added before its text.
+    pub fn write_html_with_id<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        id: &str,
+        self_link: bool,
+    ) -> std::io::Result<()> {
+        self.render_html(
+            output,
+            Some(id),
+            self_link,
+            &HashMap::new(),
+            &RenderOptions::default(),
+        )
+    }
+
+    /// Write this heading as html, with `id` as it is.  A self link needs an id to link to.
+    pub(crate) fn render_html<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        id: Option<&str>,
+        self_link: bool,
+        definitions: &HashMap<String, &str>,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        let level_tag = self.level().html_tag();
+        match id {
+            Some(id) => write!(output, "<{level_tag} id=\"{id}\">")?,
+            None => write!(output, "<{level_tag}>")?,
+        }
+        if let (Some(id), true) = (id, self_link) {
+            write!(
+                output,
+                "<a class=\"anchor\" href=\"#{id}\" aria-hidden=\"true\">#</a>"
+            )?;
+        }
+        write_html_inlines(self.text(), output, definitions, options)?;
+        write!(output, "</{level_tag}>")?;
 
         Ok(())
     }
 }
 
+impl<'source> Locate for Header<'source> {
+    fn locate(&mut self, source: &str) {
+        match self {
+            Header::AtxHeader { text, span, .. } | Header::SetextHeader { text, span, .. } => {
+                text.locate(source);
+                span.locate(source);
+            }
+        }
+    }
+}
+
+impl<'source> Header<'source> {
+    /// Write this heading as LaTeX, with H1 headings using the `base` sectioning command.
+    pub fn write_latex_with_base<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        base: Sectioning,
+    ) -> std::io::Result<()> {
+        self.render_latex(output, base, &HashMap::new(), &RenderOptions::default())
+    }
+
+    /// Write this heading as LaTeX, looking up reference-style links in `definitions`.
+    pub(crate) fn render_latex<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        base: Sectioning,
+        definitions: &HashMap<String, &str>,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        let depth = u8::from(self.level()) as usize - 1;
+        write!(output, "\\{}{{", base.nested(depth).command())?;
+        write_latex_text(self.text(), output, definitions, options)?;
+        write!(output, "}}")
+    }
+}
+
+impl<'source> AsLatex for Header<'source> {
+    fn write_latex<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+        self.write_latex_with_base(output, Sectioning::default())
+    }
+}
+
+impl<'source> AsHtml for Header<'source> {
+    fn write_html<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+        self.render_html(
+            output,
+            None,
+            false,
+            &HashMap::new(),
+            &RenderOptions::default(),
+        )
+    }
+}
+
 impl<'source> AsText for Header<'source> {
     fn write_as_text<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
         match self {
-            Header::AtxHeader { level, text } => {
+            Header::AtxHeader { level, text, .. } => {
                 match level {
                     HeadingLevel::H1 => write!(output, "# ")?,
                     HeadingLevel::H2 => write!(output, "## ")?,
@@ -98,10 +279,12 @@ impl<'source> AsText for Header<'source> {
                 level,
                 level_len,
                 text,
+                ..
             } => {
                 for t in text.iter() {
                     t.write_as_text(output)?;
                 }
+                writeln!(output)?;
 
                 let to_write = if *level == HeadingLevel::H1 { "=" } else { "-" };
                 for _ in 0..*level_len {
@@ -114,6 +297,12 @@ impl<'source> AsText for Header<'source> {
     }
 }
 
+impl<'source> std::fmt::Display for Header<'source> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_as_text_fmt(f)
+    }
+}
+
 fn setext_level_from_ending(input: &str) -> IResult<&str, HeadingLevel> {
     delimited(
         space0,
@@ -133,7 +322,7 @@ fn setext_ending(input: &str) -> IResult<&str, HeadingLevel> {
         .parse_next(input)
 }
 
-pub fn setext_header(input: &str) -> IResult<&str, Header> {
+pub fn setext_header(input: &str) -> IResult<&str, Header<'_>> {
     let Some(line) = input.lines()
         .find(|&line| setext_level_from_ending.parse_next(line).is_ok()) else {
         return fail(input);
@@ -154,13 +343,29 @@ pub fn setext_header(input: &str) -> IResult<&str, Header> {
             text,
             level_len: line_len,
             level,
+            span: Span::default(),
         })
         .parse_next(input);
 
-    x
+    x.map(|(remaining, mut header)| {
+        *header.span_mut() = Span::between(input, remaining);
+        (remaining, header)
+    })
 }
 
-pub fn parse_header(input: &'_ str) -> IResult<&str, Header> {
+/// The `#`s that start an ATX heading, followed by a space or the end of the line as CommonMark
+/// needs them to be.
+pub(crate) fn atx_opening(input: &str) -> IResult<&str, ()> {
+    (
+        space0,
+        take_while_m_n(1, 6, '#'),
+        alt((space1.void(), newline.void(), eof.void())),
+    )
+        .void()
+        .parse_next(input)
+}
+
+pub fn parse_header(input: &'_ str) -> IResult<&str, Header<'_>> {
     let find_until_opt_terminator = |ending: &'static str| {
         alt((
             take_till1("\n"),
@@ -170,38 +375,48 @@ pub fn parse_header(input: &'_ str) -> IResult<&str, Header> {
             MarkdownText::parse_markdown_text_stream,
             (space0, opt(ending), space0),
         ))
-        .context("find until opt terminator".to_string())
+        .context("find until opt terminator")
     };
 
     dispatch! {delimited(space0, take_while1("#"), space0);
         "######" => find_until_opt_terminator("######").map(|text| Header::AtxHeader {
             text,
             level: HeadingLevel::H6,
+            span: Span::default(),
         }),
         "#####" => find_until_opt_terminator("#####").map(|text| Header::AtxHeader {
             text,
             level: HeadingLevel::H5,
+            span: Span::default(),
         }),
         "####" => find_until_opt_terminator("####").map(|text| Header::AtxHeader {
             text,
             level: HeadingLevel::H4,
+            span: Span::default(),
         }),
         "###" => find_until_opt_terminator("###").map(|text| Header::AtxHeader {
             text,
             level: HeadingLevel::H3,
+            span: Span::default(),
         }),
         "##" => find_until_opt_terminator("##").map(|text| Header::AtxHeader {
             text,
             level: HeadingLevel::H2,
+            span: Span::default(),
         }),
         "#" => find_until_opt_terminator("#").map(|text| Header::AtxHeader {
             text,
             level: HeadingLevel::H1,
+            span: Span::default(),
         }),
         _ => fail
     }
     .context("atx-style header")
     .parse_next(input)
+    .map(|(remaining, mut header)| {
+        *header.span_mut() = Span::between(input, remaining);
+        (remaining, header)
+    })
 }
 
 #[cfg(test)]
@@ -209,58 +424,82 @@ mod tests {
     use winnow::FinishIResult;
 
     use super::*;
+    use crate::span::Locate;
 
     #[test]
     fn test_atx_header() {
-        let (remaining, heading) = parse_header("#Hello, World!\n").unwrap();
+        let input = "#Hello, World!\n";
+        let (remaining, mut heading) = parse_header(input).unwrap();
+        heading.locate(input);
         assert_eq!(remaining, "\n");
         assert_eq!(
             heading,
             Header::AtxHeader {
                 level: HeadingLevel::H1,
-                text: vec![MarkdownText::Text("Hello, World"), MarkdownText::Text("!")]
+                text: vec![
+                    MarkdownText::Text("Hello, World".into(), Span::new(1, 13)),
+                    MarkdownText::Text("!".into(), Span::new(13, 14))
+                ],
+                span: Span::new(0, 14),
             }
         );
     }
 
     #[test]
     fn test_setext_header_h1() {
-        let (remaining, header) = setext_header("Hello, World!\n============\n").unwrap();
+        let input = "Hello, World!\n============\n";
+        let (remaining, mut header) = setext_header(input).unwrap();
+        header.locate(input);
         assert_eq!(remaining, "\n");
         assert_eq!(
             header,
             Header::SetextHeader {
                 level: HeadingLevel::H1,
                 level_len: 12,
-                text: vec![MarkdownText::Text("Hello, World"), MarkdownText::Text("!")]
+                text: vec![
+                    MarkdownText::Text("Hello, World".into(), Span::new(0, 12)),
+                    MarkdownText::Text("!".into(), Span::new(12, 13))
+                ],
+                span: Span::new(0, 26),
             }
         );
     }
 
     #[test]
     fn test_setext_header_h2() {
-        let (remaining, header) = setext_header("Hello, World!\n------------").unwrap();
+        let input = "Hello, World!\n------------";
+        let (remaining, mut header) = setext_header(input).unwrap();
+        header.locate(input);
         assert_eq!(remaining, "");
         assert_eq!(
             header,
             Header::SetextHeader {
                 level: HeadingLevel::H2,
                 level_len: 12,
-                text: vec![MarkdownText::Text("Hello, World"), MarkdownText::Text("!")]
+                text: vec![
+                    MarkdownText::Text("Hello, World".into(), Span::new(0, 12)),
+                    MarkdownText::Text("!".into(), Span::new(12, 13))
+                ],
+                span: Span::new(0, 26),
             }
         );
     }
 
     #[test]
     fn test_atx_embedded() {
-        let header = parse_header("# this isn't a link: [foo]").finish().unwrap();
+        let input = "# this isn't a link: [foo]";
+        let mut header = parse_header(input).finish().unwrap();
+        header.locate(input);
+        let mut text: Vec<_> = MarkdownText::parse_markdown_text_stream(&input[2..])
+            .finish()
+            .unwrap();
+        text.locate(input);
         assert_eq!(
             header,
             Header::AtxHeader {
                 level: HeadingLevel::H1,
-                text: MarkdownText::parse_markdown_text_stream("this isn't a link: [foo]")
-                    .finish()
-                    .unwrap()
+                text,
+                span: Span::new(0, 26),
             }
         )
     }
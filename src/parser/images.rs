@@ -1,52 +1,150 @@
+use std::{borrow::Cow, collections::HashMap};
+
 use winnow::{
     branch::alt,
     bytes::take_until0,
     character::{multispace0, newline, space0},
     combinator::opt,
     sequence::delimited,
-    IResult, Parser,
+    Parser,
 };
 
 use crate::{
+    escape_attribute, escape_html, escape_latex, escape_latex_url,
     parser::util::{nested_brackets, nested_parenthesis},
-    AsHtml, AsText,
+    plain::image_target,
+    span::{Locate, Span},
+    unescape_markdown, AsHtml, AsLatex, AsText,
 };
 
+use super::IResult;
+
+/// Where an image is.
 #[derive(Debug, PartialEq, Eq)]
-enum ImageRef<'a> {
-    Ref(&'a str),
-    Inline(&'a str),
+pub enum ImageRef<'a> {
+    /// The label of a link definition, as in `![alt][label]`
+    Ref(Cow<'a, str>),
+    /// A url, as in `![alt](logo.png)`
+    Inline(Cow<'a, str>),
+}
+
+impl<'a> ImageRef<'a> {
+    pub fn into_owned(self) -> ImageRef<'static> {
+        match self {
+            ImageRef::Ref(label) => ImageRef::Ref(Cow::Owned(label.into_owned())),
+            ImageRef::Inline(target) => ImageRef::Inline(Cow::Owned(target.into_owned())),
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Image<'a> {
-    alt_text: &'a str,
-    image_ref: ImageRef<'a>,
-    title: Option<&'a str>,
+    pub(crate) alt_text: Cow<'a, str>,
+    pub(crate) image_ref: ImageRef<'a>,
+    pub(crate) title: Option<Cow<'a, str>>,
+    pub(crate) span: Span,
+}
+
+impl<'a> Image<'a> {
+    /// The text shown in place of the image
+    pub fn alt_text(&self) -> &str {
+        &self.alt_text
+    }
+
+    pub fn image_ref(&self) -> &ImageRef<'a> {
+        &self.image_ref
+    }
+
+    pub fn image_ref_mut(&mut self) -> &mut ImageRef<'a> {
+        &mut self.image_ref
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// The part of the source this was parsed from
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Copy this image so it no longer borrows from the source.
+    pub fn into_owned(self) -> Image<'static> {
+        Image {
+            alt_text: Cow::Owned(self.alt_text.into_owned()),
+            image_ref: self.image_ref.into_owned(),
+            title: self.title.map(|t| Cow::Owned(t.into_owned())),
+            span: self.span,
+        }
+    }
+}
+
+impl<'a> Locate for Image<'a> {
+    fn locate(&mut self, source: &str) {
+        self.span.locate(source);
+    }
+}
+
+impl<'a> Image<'a> {
+    /// Write this image as html, looking up reference-style images in `definitions`.  An image
+    /// of a label that isn't defined is written as the text it was in the source.
+    pub(crate) fn render_html<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        definitions: &HashMap<String, &str>,
+    ) -> std::io::Result<()> {
+        let Some(target) = image_target(self, definitions) else {
+            return write!(output, "{}", escape_html(&self.to_markdown()));
+        };
+
+        let alt_text = unescape_markdown(&self.alt_text);
+        write!(
+            output,
+            "<img src=\"{}\" alt=\"{}\"",
+            escape_attribute(target),
+            escape_html(&alt_text)
+        )?;
+        if let Some(title) = &self.title {
+            write!(output, " title=\"{}\"", escape_attribute(title))?;
+        }
+        write!(output, "/>")
+    }
 }
 
 impl<'a> AsHtml for Image<'a> {
     fn write_html<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
-        match self.image_ref {
-            ImageRef::Ref(_) => {
-                unimplemented!("html output for ref-style images is not yet implemented")
-            }
-            ImageRef::Inline(target) => {
-                write!(output, "<img src=\"{target}\" alt=\"{0}\"", self.alt_text)?;
-                if let Some(title) = self.title {
-                    write!(output, " title=\"{title}\"")?;
-                }
-                write!(output, "/>")?;
-            }
+        self.render_html(output, &HashMap::new())
+    }
+}
+
+impl<'a> Image<'a> {
+    /// Write this image as LaTeX, looking up reference-style images in `definitions`.  Only the
+    /// alt text of an image of a label that isn't defined is written.
+    pub(crate) fn render_latex<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        definitions: &HashMap<String, &str>,
+    ) -> std::io::Result<()> {
+        match image_target(self, definitions) {
+            Some(target) => write!(
+                output,
+                "\\includegraphics{{{}}}",
+                escape_latex_url(target)
+            ),
+            None => write!(output, "{}", escape_latex(&self.alt_text)),
         }
+    }
+}
 
-        Ok(())
+impl<'a> AsLatex for Image<'a> {
+    fn write_latex<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+        self.render_latex(output, &HashMap::new())
     }
 }
 
 impl<'a> AsText for Image<'a> {
     fn write_as_text<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
-        match self.image_ref {
+        match &self.image_ref {
             ImageRef::Ref(r) => write!(output, "![{}][{}]", self.alt_text, r)?,
             ImageRef::Inline(i) => write!(output, "![{}]({})", self.alt_text, i)?,
         }
@@ -54,7 +152,13 @@ impl<'a> AsText for Image<'a> {
     }
 }
 
-fn ref_style(input: &str) -> IResult<&str, Image> {
+impl<'a> std::fmt::Display for Image<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_as_text_fmt(f)
+    }
+}
+
+fn ref_style(input: &str) -> IResult<&str, Image<'_>> {
     (
         delimited("![", nested_brackets.recognize(), "]"),
         opt(" "),
@@ -62,15 +166,16 @@ fn ref_style(input: &str) -> IResult<&str, Image> {
         delimited("[", take_until0("]"), "]"),
     )
         .map(|x| Image {
-            alt_text: x.0,
-            image_ref: ImageRef::Ref(x.3),
+            alt_text: x.0.into(),
+            image_ref: ImageRef::Ref(x.3.into()),
             title: None,
+            span: Span::default(),
         })
         .context("ref-style image")
         .parse_next(input)
 }
 
-fn inline_style(input: &str) -> IResult<&str, Image> {
+fn inline_style(input: &str) -> IResult<&str, Image<'_>> {
     (
         delimited("![", nested_brackets.recognize(), "]"),
         opt(" "),
@@ -85,16 +190,18 @@ fn inline_style(input: &str) -> IResult<&str, Image> {
         ")",
     )
         .map(|x| Image {
-            alt_text: x.0,
-            image_ref: ImageRef::Inline(x.4),
-            title: x.6,
+            alt_text: x.0.into(),
+            image_ref: ImageRef::Inline(x.4.into()),
+            title: x.6.map(Cow::Borrowed),
+            span: Span::default(),
         })
         .context("inline image")
         .parse_next(input)
 }
 
-pub fn parse_image(input: &str) -> IResult<&str, Image> {
-    let (remaining, image) = alt((ref_style, inline_style)).parse_next(input)?;
+pub fn parse_image(input: &str) -> IResult<&str, Image<'_>> {
+    let (remaining, mut image) = alt((ref_style, inline_style)).parse_next(input)?;
+    image.span = Span::between(input, remaining);
     Ok((remaining, image))
 }
 
@@ -104,29 +211,34 @@ mod test {
 
     #[test]
     fn parse_inline() {
-        let (remaining, image) =
-            parse_image("![foo](https://github.com/favicon.ico)\n").unwrap();
+        let input = "![foo](https://github.com/favicon.ico)\n";
+        let (remaining, mut image) = parse_image(input).unwrap();
+        image.locate(input);
         assert_eq!(remaining, "\n");
         assert_eq!(
             image,
             Image {
-                alt_text: "foo",
-                image_ref: ImageRef::Inline("https://github.com/favicon.ico"),
-                title: None
+                alt_text: "foo".into(),
+                image_ref: ImageRef::Inline("https://github.com/favicon.ico".into()),
+                title: None,
+                span: Span::new(0, 38),
             }
         )
     }
 
     #[test]
     fn parse_ref() {
-        let (remaining, image) = parse_image("![foo][foo_image]\n").unwrap();
+        let input = "![foo][foo_image]\n";
+        let (remaining, mut image) = parse_image(input).unwrap();
+        image.locate(input);
         assert_eq!(remaining, "\n");
         assert_eq!(
             image,
             Image {
-                alt_text: "foo",
-                image_ref: ImageRef::Ref("foo_image"),
-                title: None
+                alt_text: "foo".into(),
+                image_ref: ImageRef::Ref("foo_image".into()),
+                title: None,
+                span: Span::new(0, 17),
             }
         )
     }
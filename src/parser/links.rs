@@ -1,55 +1,171 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::HashMap};
 
 use winnow::{
     branch::alt,
-    bytes::{none_of, tag_no_case, take_until0, take_until1},
-    character::{multispace0, newline, space0},
-    combinator::opt,
+    bytes::{none_of, tag_no_case, take_till1, take_until0, take_until1, take_while_m_n},
+    character::{multispace0, newline, space0, space1},
+    combinator::{eof, opt, peek},
     multi::many1,
-    sequence::delimited,
+    sequence::{delimited, preceded},
     stream::Accumulate,
-    IResult, Parser,
+    Parser,
 };
 
 use crate::{
-    parser::util::{nested_brackets, nested_parenthesis},
-    AsHtml, AsText,
+    escape_attribute, escape_html, escape_latex_url,
+    parser::util::{nested_brackets, nested_parenthesis, write_html_inlines, write_latex_text},
+    plain::link_target,
+    render::RenderOptions,
+    span::{Locate, Span},
+    AsHtml, AsLatex, AsText,
 };
 
-use super::util::MarkdownText;
+use super::{util::MarkdownText, IResult};
 
+/// Where a link points to.
 #[derive(Debug, PartialEq, Eq)]
-enum LinkRef<'a> {
-    Ref(&'a str),
-    Inline(&'a str),
+pub enum LinkRef<'a> {
+    /// The label of a link definition, as in `[text][label]`
+    Ref(Cow<'a, str>),
+    /// A url, as in `[text](https://example.com)`
+    Inline(Cow<'a, str>),
+}
+
+impl<'a> LinkRef<'a> {
+    pub fn into_owned(self) -> LinkRef<'static> {
+        match self {
+            LinkRef::Ref(label) => LinkRef::Ref(Cow::Owned(label.into_owned())),
+            LinkRef::Inline(target) => LinkRef::Inline(Cow::Owned(target.into_owned())),
+        }
+    }
 }
 
 /// This is a link with a title: [link](https://example.com "title")
 #[derive(Debug, PartialEq, Eq)]
 pub struct Link<'source> {
-    link_text: Vec<MarkdownText<'source>>,
-    link_ref: LinkRef<'source>,
-    title: Option<&'source str>,
+    pub(crate) link_text: Vec<MarkdownText<'source>>,
+    pub(crate) link_ref: LinkRef<'source>,
+    pub(crate) title: Option<Cow<'source, str>>,
+    pub(crate) span: Span,
+}
+
+impl<'source> Link<'source> {
+    /// The text of the link, which can have other inline content in it
+    pub fn link_text(&self) -> &[MarkdownText<'source>] {
+        &self.link_text
+    }
+
+    pub fn link_text_mut(&mut self) -> &mut Vec<MarkdownText<'source>> {
+        &mut self.link_text
+    }
+
+    /// Where the link points to
+    pub fn link_ref(&self) -> &LinkRef<'source> {
+        &self.link_ref
+    }
+
+    pub fn link_ref_mut(&mut self) -> &mut LinkRef<'source> {
+        &mut self.link_ref
+    }
+
+    /// The title of the link, for links written inline with one
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// The part of the source this was parsed from
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Copy this link so it no longer borrows from the source.
+    pub fn into_owned(self) -> Link<'static> {
+        Link {
+            link_text: MarkdownText::into_owned_all(self.link_text),
+            link_ref: self.link_ref.into_owned(),
+            title: self.title.map(|t| Cow::Owned(t.into_owned())),
+            span: self.span,
+        }
+    }
+}
+
+impl<'source> Locate for Link<'source> {
+    fn locate(&mut self, source: &str) {
+        self.link_text.locate(source);
+        self.span.locate(source);
+    }
+}
+
+impl<'source> Link<'source> {
+    /// Write this link as html, looking up reference-style links in `definitions`.  A link to a
+    /// label that isn't defined is written as the text it was in the source.
+    pub(crate) fn render_html<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        definitions: &HashMap<String, &str>,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        let Some(target) = link_target(self, definitions) else {
+            write!(output, "[")?;
+            write_html_inlines(&self.link_text, output, definitions, options)?;
+            if let LinkRef::Ref(label) = &self.link_ref {
+                write!(output, "][{}]", escape_html(label))?;
+            }
+            return Ok(());
+        };
+
+        write!(output, "<a href=\"{}\"", escape_attribute(target))?;
+        if let Some(title) = &self.title {
+            write!(output, " title=\"{}\"", escape_attribute(title))?;
+        }
+        write_link_target(output, options)?;
+        write!(output, ">")?;
+        write_html_inlines(&self.link_text, output, definitions, options)?;
+        write!(output, "</a>")
+    }
 }
 
 impl<'source> AsHtml for Link<'source> {
     fn write_html<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
-        match self.link_ref {
-            LinkRef::Ref(_) => unimplemented!("TODO: We need to do some post-processing before we can write reference-style links"),
-            LinkRef::Inline(target) => {
-                write!(output, "<a href=\"{target}\"")?;
-                if let Some(title) = self.title {
-                    write!(output, " title=\"{title}\"")?;
-                }
-                write!(output, ">")?;
-                for t in self.link_text.iter() {
-                    t.write_html(output)?;
-                }
-                write!(output, "</a>")?;
-            },
-        }
+        self.render_html(output, &HashMap::new(), &RenderOptions::default())
+    }
+}
 
-        Ok(())
+/// Ask for a link to be opened in a new tab, if `options` say so.  `noopener` stops the new page
+/// from getting at the one that opened it.
+fn write_link_target<Writer: std::io::Write>(
+    output: &mut Writer,
+    options: &RenderOptions,
+) -> std::io::Result<()> {
+    if options.links_in_new_tab {
+        write!(output, " target=\"_blank\" rel=\"noopener\"")?;
+    }
+
+    Ok(())
+}
+
+impl<'source> Link<'source> {
+    /// Write this link as LaTeX, looking up reference-style links in `definitions`.  Only the
+    /// text of a link to a label that isn't defined is written.
+    pub(crate) fn render_latex<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        definitions: &HashMap<String, &str>,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        let Some(target) = link_target(self, definitions) else {
+            return write_latex_text(&self.link_text, output, definitions, options);
+        };
+
+        write!(output, "\\href{{{}}}{{", escape_latex_url(target))?;
+        write_latex_text(&self.link_text, output, definitions, options)?;
+        write!(output, "}}")
+    }
+}
+
+impl<'source> AsLatex for Link<'source> {
+    fn write_latex<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+        self.render_latex(output, &HashMap::new(), &RenderOptions::default())
     }
 }
 
@@ -60,11 +176,11 @@ impl<'source> AsText for Link<'source> {
             t.write_as_text(output)?;
         }
         write!(output, "]")?;
-        match self.link_ref {
+        match &self.link_ref {
             LinkRef::Ref(r) => write!(output, "[{r}]")?,
             LinkRef::Inline(i) => {
                 write!(output, "({i}")?;
-                if let Some(title) = self.title {
+                if let Some(title) = &self.title {
                     write!(output, " \"{title}\"")?;
                 }
                 write!(output, ")")?;
@@ -74,26 +190,187 @@ impl<'source> AsText for Link<'source> {
     }
 }
 
+impl<'source> std::fmt::Display for Link<'source> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_as_text_fmt(f)
+    }
+}
+
 /// A link where the target is the same as the text.  In markdown, this is constructed with
-/// `<https://example.com>` (rendered as <https://example.com>).
+/// `<https://example.com>` (rendered as <https://example.com>), or in GFM with just the url.
 #[derive(Debug, PartialEq, Eq)]
 pub struct AutoLink<'a> {
-    target: Cow<'a, str>,
+    pub(crate) target: Cow<'a, str>,
+    /// Written as a bare url, without the angle brackets
+    pub(crate) literal: bool,
+    pub(crate) span: Span,
+}
+
+impl<'a> AutoLink<'a> {
+    /// The url or email address this links to, which is also its text
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// Where this links to.  This is the target, except for bare urls starting with `www.`,
+    /// which are taken to be `http://` ones.
+    pub fn url(&self) -> Cow<'_, str> {
+        match self.literal && self.target.starts_with("www.") {
+            true => format!("http://{}", self.target).into(),
+            false => Cow::Borrowed(&self.target),
+        }
+    }
+
+    /// Whether this was written as a bare url, without angle brackets around it
+    pub fn is_literal(&self) -> bool {
+        self.literal
+    }
+
+    /// The part of the source this was parsed from
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn into_owned(self) -> AutoLink<'static> {
+        AutoLink {
+            target: Cow::Owned(self.target.into_owned()),
+            literal: self.literal,
+            span: self.span,
+        }
+    }
+}
+
+impl<'a> Locate for AutoLink<'a> {
+    fn locate(&mut self, source: &str) {
+        self.span.locate(source);
+    }
+}
+
+impl<'a> AutoLink<'a> {
+    pub(crate) fn render_html<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        write!(output, "<a href=\"{}\"", escape_attribute(&self.url()))?;
+        write_link_target(output, options)?;
+        write!(output, ">{}</a>", escape_html(&self.target))
+    }
 }
 
 impl<'a> AsHtml for AutoLink<'a> {
     fn write_html<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
-        write!(output, "<a href=\"{}\">{}</a>", self.target, self.target)
+        self.render_html(output, &RenderOptions::default())
+    }
+}
+
+impl<'a> AsLatex for AutoLink<'a> {
+    fn write_latex<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+        write!(output, "\\url{{{}}}", escape_latex_url(&self.url()))
     }
 }
 
 impl<'a> AsText for AutoLink<'a> {
     fn write_as_text<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
-        write!(output, "<{}>", self.target)?;
+        match self.literal {
+            true => write!(output, "{}", self.target)?,
+            false => write!(output, "<{}>", self.target)?,
+        }
+        Ok(())
+    }
+}
+
+impl<'a> std::fmt::Display for AutoLink<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_as_text_fmt(f)
+    }
+}
+
+/// A link reference definition, which gives the target for reference-style links and images with
+/// the same label: `[label]: https://example.com "title"`
+#[derive(Debug, PartialEq, Eq)]
+pub struct LinkDefinition<'source> {
+    pub(crate) label: Cow<'source, str>,
+    pub(crate) target: Cow<'source, str>,
+    pub(crate) title: Option<Cow<'source, str>>,
+    pub(crate) span: Span,
+}
+
+impl<'source> LinkDefinition<'source> {
+    /// The label that links refer to this definition by, as it was written
+    pub fn label(&self) -> &str {
+        &self.label
+    }
+
+    /// The url that links with this label point to
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// The part of the source this was parsed from
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    pub fn into_owned(self) -> LinkDefinition<'static> {
+        LinkDefinition {
+            label: Cow::Owned(self.label.into_owned()),
+            target: Cow::Owned(self.target.into_owned()),
+            title: self.title.map(|t| Cow::Owned(t.into_owned())),
+            span: self.span,
+        }
+    }
+}
+
+impl<'source> Locate for LinkDefinition<'source> {
+    fn locate(&mut self, source: &str) {
+        self.span.locate(source);
+    }
+}
+
+impl<'source> AsHtml for LinkDefinition<'source> {
+    fn write_html<Writer: std::io::Write>(&self, _output: &mut Writer) -> std::io::Result<()> {
+        // definitions don't render to anything, they're only used to resolve links
+        Ok(())
+    }
+}
+
+impl<'source> AsLatex for LinkDefinition<'source> {
+    fn write_latex<Writer: std::io::Write>(&self, _output: &mut Writer) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'source> AsText for LinkDefinition<'source> {
+    fn write_as_text<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+        write!(output, "[{}]: {}", self.label, self.target)?;
+        if let Some(title) = &self.title {
+            write!(output, " \"{title}\"")?;
+        }
         Ok(())
     }
 }
 
+impl<'source> std::fmt::Display for LinkDefinition<'source> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_as_text_fmt(f)
+    }
+}
+
+/// Normalize a link label so that labels can be compared the way markdown does: case-insensitive,
+/// with runs of whitespace treated as a single space.
+pub(crate) fn normalize_label(label: &str) -> String {
+    label
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
 fn parse_brackets<'source, A>(input: &'source str) -> IResult<&'source str, A>
 where
     A: Accumulate<MarkdownText<'source>>,
@@ -108,7 +385,7 @@ where
     .parse_next(input)
 }
 
-fn ref_style(input: &str) -> IResult<&str, Link> {
+fn ref_style(input: &str) -> IResult<&str, Link<'_>> {
     (
         parse_brackets,
         opt(" "),
@@ -117,14 +394,15 @@ fn ref_style(input: &str) -> IResult<&str, Link> {
     )
         .map(|x| Link {
             link_text: x.0,
-            link_ref: LinkRef::Ref(x.3),
+            link_ref: LinkRef::Ref(x.3.into()),
             title: None,
+            span: Span::default(),
         })
         .context("ref-style link")
         .parse_next(input)
 }
 
-fn inline_style(input: &str) -> IResult<&str, Link> {
+fn inline_style(input: &str) -> IResult<&str, Link<'_>> {
     (
         parse_brackets,
         opt(" "),
@@ -146,14 +424,15 @@ fn inline_style(input: &str) -> IResult<&str, Link> {
     )
         .map(|x| Link {
             link_text: x.0,
-            link_ref: LinkRef::Inline(x.2 .0),
-            title: x.2 .1,
+            link_ref: LinkRef::Inline(x.2 .0.into()),
+            title: x.2 .1.map(Cow::Borrowed),
+            span: Span::default(),
         })
         .context("inline link")
         .parse_next(input)
 }
 
-pub fn parse_auto_link(input: &str) -> IResult<&str, AutoLink> {
+pub fn parse_auto_link(input: &str) -> IResult<&str, AutoLink<'_>> {
     let email = delimited(
         "<",
         (
@@ -167,6 +446,8 @@ pub fn parse_auto_link(input: &str) -> IResult<&str, AutoLink> {
     .context("email autolink")
     .map(|x| AutoLink {
         target: Cow::Owned(format!("mailto:{}@{}", x.1, x.3)),
+        literal: false,
+        span: Span::default(),
     });
     let normal = delimited(
         "<",
@@ -186,13 +467,50 @@ pub fn parse_auto_link(input: &str) -> IResult<&str, AutoLink> {
     .context("normal autolink")
     .map(|x| AutoLink {
         target: Cow::Borrowed(x),
+        literal: false,
+        span: Span::default(),
     });
 
-    alt((email, normal)).parse_next(input)
+    let (remaining, mut link) = alt((email, normal)).parse_next(input)?;
+    link.span = Span::between(input, remaining);
+    Ok((remaining, link))
+}
+
+pub fn parse_link_definition(input: &str) -> IResult<&str, LinkDefinition<'_>> {
+    (
+        take_while_m_n(0, 3, ' '),
+        delimited("[", take_till1("[]\n"), "]:"),
+        space0,
+        alt((delimited("<", take_till1(">\n"), ">"), take_till1(" \t\n"))),
+        opt(preceded(
+            space1,
+            alt((
+                delimited("\"", take_till1("\"\n"), "\""),
+                delimited("\'", take_till1("\'\n"), "\'"),
+                delimited("(", take_till1(")\n"), ")"),
+            )),
+        )),
+        space0,
+        peek(alt((newline.void(), eof.void()))),
+    )
+        .map(|x| LinkDefinition {
+            label: Cow::Borrowed(x.1),
+            target: Cow::Borrowed(x.3),
+            title: x.4.map(Cow::Borrowed),
+            span: Span::default(),
+        })
+        .context("link definition")
+        .parse_next(input)
+        .map(|(remaining, mut definition)| {
+            definition.span = Span::between(input, remaining);
+            (remaining, definition)
+        })
 }
 
-pub fn parse_link(input: &str) -> IResult<&str, Link> {
-    alt((ref_style, inline_style)).parse_next(input)
+pub fn parse_link(input: &str) -> IResult<&str, Link<'_>> {
+    let (remaining, mut link) = alt((ref_style, inline_style)).parse_next(input)?;
+    link.span = Span::between(input, remaining);
+    Ok((remaining, link))
 }
 
 #[cfg(test)]
@@ -203,55 +521,67 @@ mod test {
 
     #[test]
     fn parse_inline() {
-        let (remaining, link) = parse_link("[foo](https://github.com/)\n").unwrap();
+        let input = "[foo](https://github.com/)\n";
+        let (remaining, mut link) = parse_link(input).unwrap();
+        link.locate(input);
         assert_eq!(remaining, "\n");
         assert_eq!(
             link,
             Link {
-                link_text: vec![MarkdownText::Text("foo")],
-                link_ref: LinkRef::Inline("https://github.com/"),
-                title: None
+                link_text: vec![MarkdownText::Text("foo".into(), Span::new(1, 4))],
+                link_ref: LinkRef::Inline("https://github.com/".into()),
+                title: None,
+                span: Span::new(0, 26),
             }
         )
     }
 
     #[test]
     fn parse_ref() {
-        let (remaining, link) = parse_link("[foo][foo_link]\n").unwrap();
+        let input = "[foo][foo_link]\n";
+        let (remaining, mut link) = parse_link(input).unwrap();
+        link.locate(input);
         assert_eq!(remaining, "\n");
         assert_eq!(
             link,
             Link {
-                link_text: vec![MarkdownText::Text("foo")],
-                link_ref: LinkRef::Ref("foo_link"),
-                title: None
+                link_text: vec![MarkdownText::Text("foo".into(), Span::new(1, 4))],
+                link_ref: LinkRef::Ref("foo_link".into()),
+                title: None,
+                span: Span::new(0, 15),
             }
         )
     }
 
     #[test]
     fn parse_with_brackets() {
-        let link = parse_link("[foo [bar]](https://lib.rs)").finish().unwrap();
+        let input = "[foo [bar]](https://lib.rs)";
+        let mut link = parse_link(input).finish().unwrap();
+        link.locate(input);
         assert_eq!(
             link,
             Link {
                 link_text: vec![
-                    MarkdownText::Text("foo "),
-                    MarkdownText::Text("["),
-                    MarkdownText::Text("bar"),
-                    MarkdownText::Text("]"),
+                    MarkdownText::Text("foo ".into(), Span::new(1, 5)),
+                    MarkdownText::Text("[".into(), Span::new(5, 6)),
+                    MarkdownText::Text("bar".into(), Span::new(6, 9)),
+                    MarkdownText::Text("]".into(), Span::new(9, 10)),
                 ],
-                link_ref: LinkRef::Inline("https://lib.rs"),
+                link_ref: LinkRef::Inline("https://lib.rs".into()),
                 title: None,
+                span: Span::new(0, 27),
             }
         )
     }
 
     #[test]
     fn auto_link() {
-        let (remaining, link) = parse_auto_link("<https://lib.rs>").unwrap();
+        let input = "<https://lib.rs>";
+        let (remaining, mut link) = parse_auto_link(input).unwrap();
+        link.locate(input);
         assert_eq!(remaining, "");
         assert_eq!(link.target, "https://lib.rs");
+        assert_eq!(link.span(), Span::new(0, 16));
     }
 
     #[test]
@@ -268,8 +598,107 @@ mod test {
         assert_eq!(link.target, "mailto:noreply@example.com");
     }
 
+    #[test]
+    fn link_definition() {
+        let input = "[Foo Bar]: https://example.com \"title\"\nmore";
+        let (remaining, mut definition) = parse_link_definition(input).unwrap();
+        definition.locate(input);
+        assert_eq!(remaining, "\nmore");
+        assert_eq!(
+            definition,
+            LinkDefinition {
+                label: "Foo Bar".into(),
+                target: "https://example.com".into(),
+                title: Some("title".into()),
+                span: Span::new(0, 38),
+            }
+        );
+        assert_eq!(normalize_label(&definition.label), "foo bar");
+    }
+
+    #[test]
+    fn link_definition_angle_brackets() {
+        let definition = parse_link_definition("  [foo]: <https://example.com> (title)")
+            .finish()
+            .unwrap();
+        assert_eq!(definition.target, "https://example.com");
+        assert_eq!(definition.title(), Some("title"));
+    }
+
+    #[test]
+    fn not_link_definition() {
+        assert!(parse_link_definition("[foo]: https://example.com and more").is_err());
+        assert!(parse_link_definition("[foo] not a definition").is_err());
+    }
+
     #[test]
     fn not_auto_link() {
         assert!(parse_auto_link("<noreply>").is_err())
     }
+
+    #[test]
+    fn inspecting_a_document() {
+        use crate::{
+            parser::{block::Block, headers::HeadingLevel, images::ImageRef},
+            Markdown,
+        };
+
+        let md = Markdown::parse(
+            "## The `md2md` docs\n\nSee [the *guide*](/guide), ![logo][l] and \
+             <https://x.y>.\n\n[l]: logo.png",
+        );
+        let Block::Heading(heading) = &md.blocks()[0] else {
+            panic!("expected a heading, found {:?}", md.blocks()[0]);
+        };
+        assert_eq!(heading.level(), HeadingLevel::H2);
+        assert_eq!(heading.plain_text(), "The md2md docs");
+
+        let links: Vec<_> = md
+            .inlines()
+            .filter_map(|t| match t {
+                MarkdownText::Link(link) => Some(link),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(links.len(), 1);
+        assert_eq!(links[0].link_ref(), &LinkRef::Inline("/guide".into()));
+        assert_eq!(links[0].title(), None);
+        assert_eq!(
+            links[0].link_text(),
+            [MarkdownText::Text("the *guide*".into(), Span::new(26, 37))]
+        );
+
+        let text: Vec<_> = md
+            .inlines()
+            .filter_map(|t| match t {
+                MarkdownText::Text(text, _) => Some(text.as_ref()),
+                MarkdownText::Image(image) => Some(image.alt_text()),
+                MarkdownText::AutoLink(link) => Some(link.target()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            text,
+            [
+                "The ",
+                " docs",
+                "See ",
+                "the *guide*",
+                ", ",
+                "logo",
+                " and ",
+                "https://x.y",
+                "."
+            ]
+        );
+
+        let MarkdownText::Image(image) = &md.blocks()[2].text()[3] else {
+            panic!("expected an image, found {:?}", md.blocks()[2].text()[3]);
+        };
+        assert_eq!(image.image_ref(), &ImageRef::Ref("l".into()));
+        let Block::LinkDefinition(definition) = &md.blocks()[4] else {
+            panic!("expected a link definition, found {:?}", md.blocks()[4]);
+        };
+        assert_eq!((definition.label(), definition.target()), ("l", "logo.png"));
+    }
 }
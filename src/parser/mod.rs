@@ -1,7 +1,162 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+use winnow::error::{ContextError, ErrorKind, ParseError};
+
+#[cfg(feature = "gfm")]
+pub(crate) mod autolinks;
 pub mod block;
 pub mod code;
+pub mod comments;
+pub mod front_matter;
 pub mod headers;
 pub mod images;
 pub mod links;
 pub mod paragraphs;
 pub mod util;
+
+/// A flavour of markdown, which decides the [`ParseOptions`] used to read a document.  Dialects
+/// only differ in which of those options they turn on; the rest of the syntax is read the same
+/// way by all of them.
+#[derive(Deserialize, Serialize, Default, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Dialect {
+    /// What md2md has always read, which is CommonMark with a few leniencies from the original
+    /// Markdown
+    #[default]
+    Md2md,
+    /// The original Markdown, as described by John Gruber
+    Original,
+    CommonMark,
+    /// GitHub Flavored Markdown.  Its extensions need the `gfm` feature.
+    Gfm,
+}
+
+impl FromStr for Dialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "md2md" => Ok(Dialect::Md2md),
+            "original" => Ok(Dialect::Original),
+            "commonmark" => Ok(Dialect::CommonMark),
+            "gfm" => Ok(Dialect::Gfm),
+            _ => Err(format!(
+                "expected one of `md2md`, `original`, `commonmark` or `gfm`, found `{s}`"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dialect::Md2md => write!(f, "md2md"),
+            Dialect::Original => write!(f, "original"),
+            Dialect::CommonMark => write!(f, "commonmark"),
+            Dialect::Gfm => write!(f, "gfm"),
+        }
+    }
+}
+
+/// Settings for reading a document, so the same text can be read the way different flavours of
+/// markdown would.  Start from the [`Dialect`] you want and change what you need.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Read `#heading`, without a space after the `#`s, as a heading like the original Markdown
+    /// does.  Otherwise, it's text.
+    pub headings_without_space: bool,
+    /// Read yaml or toml front matter at the start of the document.  Its metadata needs the
+    /// `front-matter` feature.
+    pub front_matter: bool,
+    /// Turn bare urls, like `https://example.com` and `www.example.com`, into links
+    #[cfg(feature = "gfm")]
+    pub autolink_literals: bool,
+}
+
+/// Syntax that can be turned on by name, on top of what a [`Dialect`] reads.  Each one turns on
+/// the [`ParseOptions`] field of the same name.
+///
+/// Only syntax the parser can read is listed here.  The GitHub extensions need the `gfm` feature,
+/// and without it their names are rejected like any other unknown name.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "kebab-case")]
+pub enum Extension {
+    HeadingsWithoutSpace,
+    FrontMatter,
+    #[cfg(feature = "gfm")]
+    AutolinkLiterals,
+}
+
+impl ParseOptions {
+    /// Turn on the syntax for `extension`.
+    pub fn enable(&mut self, extension: Extension) {
+        match extension {
+            Extension::HeadingsWithoutSpace => self.headings_without_space = true,
+            Extension::FrontMatter => self.front_matter = true,
+            #[cfg(feature = "gfm")]
+            Extension::AutolinkLiterals => self.autolink_literals = true,
+        }
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> Self {
+        Dialect::default().into()
+    }
+}
+
+impl From<Dialect> for ParseOptions {
+    fn from(dialect: Dialect) -> Self {
+        Self {
+            headings_without_space: matches!(dialect, Dialect::Md2md | Dialect::Original),
+            front_matter: matches!(dialect, Dialect::Md2md | Dialect::Gfm),
+            #[cfg(feature = "gfm")]
+            autolink_literals: dialect == Dialect::Gfm,
+        }
+    }
+}
+
+/// The result of running one of the parsers in this module.
+pub type IResult<I, O, E = Error<I>> = winnow::IResult<I, O, E>;
+
+/// Why a parser failed, along with what it was in the middle of parsing at the time.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error<I> {
+    /// The input that couldn't be parsed
+    pub input: I,
+    pub kind: ErrorKind,
+    /// The `.context(...)` of each parser that was running, from the innermost to the outermost
+    pub context: Vec<&'static str>,
+}
+
+impl<'source> ParseError<&'source str> for Error<&'source str> {
+    fn from_error_kind(input: &'source str, kind: ErrorKind) -> Self {
+        Self {
+            input,
+            kind,
+            context: vec![],
+        }
+    }
+
+    fn append(self, _input: &'source str, _kind: ErrorKind) -> Self {
+        self
+    }
+
+    /// Keep the error from whichever branch got the furthest, since that's usually the one the
+    /// author meant to write.
+    fn or(self, other: Self) -> Self {
+        if other.input.as_ptr() >= self.input.as_ptr() {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl<'source> ContextError<&'source str> for Error<&'source str> {
+    fn add_context(mut self, _input: &'source str, context: &'static str) -> Self {
+        self.context.push(context);
+        self
+    }
+}
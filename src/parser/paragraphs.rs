@@ -1,29 +1,79 @@
+use std::collections::HashMap;
+
 use winnow::{
     branch::alt,
     bytes::{one_of, take_until1},
     character::newline,
-    error::{ErrMode::Backtrack, Error, ParseError},
+    error::{ErrMode::Backtrack, ParseError},
     multi::count,
     trace::trace,
-    IResult, Parser,
+    Parser,
 };
 
-use crate::{AsHtml, AsText};
+use crate::{
+    format::indented_code,
+    render::RenderOptions,
+    span::{Locate, Span},
+    AsHtml, AsLatex, AsText,
+};
 
-use super::util::MarkdownText;
+use super::{
+    headers::atx_opening,
+    util::{write_html_inlines, write_latex_text, MarkdownText},
+    Error, IResult, ParseOptions,
+};
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Paragraph<'source> {
     pub(crate) text: Vec<MarkdownText<'source>>,
+    pub(crate) span: Span,
 }
 
-impl<'source> AsHtml for Paragraph<'source> {
-    fn write_html<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
-        for t in self.text.iter() {
-            t.write_html(output)?;
+impl<'source> Paragraph<'source> {
+    /// The content of this paragraph
+    pub fn text(&self) -> &[MarkdownText<'source>] {
+        &self.text
+    }
+
+    pub fn text_mut(&mut self) -> &mut Vec<MarkdownText<'source>> {
+        &mut self.text
+    }
+
+    /// The part of the source this was parsed from
+    pub fn span(&self) -> Span {
+        self.span
+    }
+
+    /// Copy this paragraph so it no longer borrows from the source.
+    pub fn into_owned(self) -> Paragraph<'static> {
+        Paragraph {
+            text: MarkdownText::into_owned_all(self.text),
+            span: self.span,
         }
+    }
+}
 
-        Ok(())
+impl<'source> Locate for Paragraph<'source> {
+    fn locate(&mut self, source: &str) {
+        self.text.locate(source);
+        self.span.locate(source);
+    }
+}
+
+impl<'source> Paragraph<'source> {
+    pub(crate) fn render_html<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        definitions: &HashMap<String, &str>,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        write_html_inlines(&self.text, output, definitions, options)
+    }
+}
+
+impl<'source> AsHtml for Paragraph<'source> {
+    fn write_html<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+        self.render_html(output, &HashMap::new(), &RenderOptions::default())
     }
 }
 
@@ -37,12 +87,48 @@ impl<'source> AsText for Paragraph<'source> {
     }
 }
 
-pub fn find_next<'a, F, O>(mut parser: F) -> impl FnMut(&'a str) -> IResult<&'a str, O, winnow::error::Error<&'a str>>
+impl<'source> std::fmt::Display for Paragraph<'source> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_as_text_fmt(f)
+    }
+}
+
+impl<'source> Paragraph<'source> {
+    /// Write this paragraph as LaTeX, looking up reference-style links in `definitions`.
+    pub(crate) fn render_latex<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        definitions: &HashMap<String, &str>,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        if let Some(lines) = indented_code(&self.text) {
+            writeln!(output, "\\begin{{verbatim}}")?;
+            for line in lines.iter() {
+                writeln!(output, "{line}")?;
+            }
+            write!(output, "\\end{{verbatim}}")?;
+            if matches!(self.text.last(), Some(MarkdownText::SoftBreak(_))) {
+                writeln!(output)?;
+            }
+            return Ok(());
+        }
+
+        write_latex_text(&self.text, output, definitions, options)
+    }
+}
+
+impl<'source> AsLatex for Paragraph<'source> {
+    fn write_latex<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+        self.render_latex(output, &HashMap::new(), &RenderOptions::default())
+    }
+}
+
+pub fn find_next<'a, F, O>(mut parser: F) -> impl FnMut(&'a str) -> IResult<&'a str, O, Error<&'a str>>
 where
-    F: Parser<&'a str, O, winnow::error::Error<&'a str>>,
+    F: Parser<&'a str, O, Error<&'a str>>,
 {
     trace("find_next", move |input: &'a str| {
-        for i in 0..input.len() {
+        for i in (0..input.len()).filter(|&i| input.is_char_boundary(i)) {
             let (_, rest) = input.split_at(i);
             if let Ok((_remaining, result)) = parser.parse_next(rest) {
                 return Ok((rest, result));
@@ -54,11 +140,11 @@ where
 
 /// Takes until the given parser matches the input stream.  If the parser never matches, the input
 /// is consumed.  The result of the given parser is not consumed.
-pub fn take_until_match<'a, F, O>(mut parser: F) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str, winnow::error::Error<&'a str>>
-where F: Parser<&'a str, O, winnow::error::Error<&'a str>>
+pub fn take_until_match<'a, F, O>(mut parser: F) -> impl FnMut(&'a str) -> IResult<&'a str, &'a str, Error<&'a str>>
+where F: Parser<&'a str, O, Error<&'a str>>
 {
     trace("take_until_matches", move |input: &'a str| {
-        for i in 0..=input.len() {
+        for i in (0..=input.len()).filter(|&i| input.is_char_boundary(i)) {
             let (first, rest) = input.split_at(i);
             if parser.parse_next(rest).is_ok() {
                 return Ok((rest, first));
@@ -73,27 +159,49 @@ where F: Parser<&'a str, O, winnow::error::Error<&'a str>>
 /// In markdown, a paragraph is one or more lines of markdown text.  Unlike other block types,
 /// there isn't any special characters to delineate this block type from others, so blocks should
 /// default to this.
-pub fn parse_paragraph(input: &str) -> IResult<&str, Paragraph> {
-    let block_termination_chars = "=-#";
-    let mut stream_parser = MarkdownText::parse_markdown_text_stream
-        .map(|text| Paragraph { text })
-        .context("paragraph");
-
-    match find_next(alt((
-        count(newline, 2).map(|_: ()| {}).context("2 newlines"),
-        (
-            newline::<&str, Error<&str>>,
-            one_of(block_termination_chars),
-        )
-            .recognize()
-            .context("searching for header characters")
-            .void(),
-    )).recognize())
-    .parse_next(input)
-    {
-        Ok((_, text)) => take_until1(text)
-            .and_then(stream_parser)
-            .parse_next(input),
-        Err(_) => stream_parser.parse_next(input),
+///
+/// A line that starts with a `#` ends the paragraph, unless `options` say that a heading needs a
+/// space after its `#`s and this line doesn't have one.
+pub fn parse_paragraph<'a>(
+    options: &ParseOptions,
+) -> impl FnMut(&'a str) -> IResult<&'a str, Paragraph<'a>> {
+    let needs_space = !options.headings_without_space;
+    let heading = move |input: &'a str| -> IResult<&'a str, ()> {
+        match needs_space {
+            true => atx_opening(input),
+            false => one_of::<_, _, Error<&str>>('#').void().parse_next(input),
+        }
+    };
+
+    move |input: &'a str| {
+        let mut stream_parser = MarkdownText::parse_markdown_text_stream
+            .map(|text| Paragraph {
+                text,
+                span: Span::default(),
+            })
+            .context("paragraph");
+
+        let (remaining, mut paragraph) = match find_next(alt((
+            count(newline, 2).map(|_: ()| {}).context("2 newlines"),
+            (
+                newline::<&str, Error<&str>>,
+                alt((one_of("=-").void(), heading)),
+            )
+                .recognize()
+                .context("searching for header characters")
+                .void(),
+            (newline, "<!--")
+                .context("searching for comments")
+                .void(),
+        )).recognize())
+        .parse_next(input)
+        {
+            Ok((_, text)) => take_until1(text)
+                .and_then(stream_parser)
+                .parse_next(input),
+            Err(_) => stream_parser.parse_next(input),
+        }?;
+        paragraph.span = Span::between(input, remaining);
+        Ok((remaining, paragraph))
     }
 }
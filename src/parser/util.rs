@@ -1,4 +1,4 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, collections::HashMap};
 
 use winnow::{
     branch::alt,
@@ -9,58 +9,291 @@ use winnow::{
     multi::{many0, many1},
     sequence::{delimited, terminated},
     stream::{Accumulate, ContainsToken, Stream},
-    IResult, Parser,
+    Parser,
 };
 
-use crate::{AsHtml, AsText};
+use crate::{
+    escape_ampersands, escape_html, escape_latex,
+    plain::strip_markup,
+    render::{RenderOptions, SoftBreak},
+    span::{Locate, Span},
+    AsHtml, AsLatex, AsText,
+};
 
 use super::{
     code::parse_inline_code,
     images::{parse_image, Image},
     links::{parse_auto_link, parse_link, AutoLink, Link},
+    IResult,
 };
 
 /// The various kinds of text that we can parse
 #[derive(Debug, PartialEq, Eq)]
 pub enum MarkdownText<'source> {
-    Text(&'source str),
+    Text(Cow<'source, str>, Span),
     Image(Image<'source>),
     Link(Link<'source>),
     AutoLink(AutoLink<'source>),
-    SoftBreak,
-    Code { code: Cow<'source, str> },
+    SoftBreak(Span),
+    Code {
+        code: Cow<'source, str>,
+        span: Span,
+    },
 }
 
-impl<'source> AsHtml for MarkdownText<'source> {
-    fn write_html<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+impl<'source> MarkdownText<'source> {
+    pub(crate) fn render_html<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        definitions: &HashMap<String, &str>,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
         match self {
-            MarkdownText::Text(text) => write!(output, "{text}")?,
-            MarkdownText::Image(image) => image.write_html(output)?,
-            MarkdownText::Link(link) => link.write_html(output)?,
-            MarkdownText::AutoLink(auto_link) => auto_link.write_html(output)?,
-            MarkdownText::SoftBreak => writeln!(output)?,
-            MarkdownText::Code { code } => write!(output, "<code>{code}</code>")?,
+            MarkdownText::Text(text, _) => write_html_text(text, output)?,
+            MarkdownText::Image(image) => image.render_html(output, definitions)?,
+            MarkdownText::Link(link) => link.render_html(output, definitions, options)?,
+            MarkdownText::AutoLink(auto_link) => auto_link.render_html(output, options)?,
+            MarkdownText::SoftBreak(_) => match options.soft_break {
+                SoftBreak::Newline => writeln!(output)?,
+                SoftBreak::Space => write!(output, " ")?,
+            },
+            MarkdownText::Code { code, .. } => {
+                write!(output, "<code>{}</code>", escape_html(code))?
+            }
         }
 
         Ok(())
     }
 }
 
+impl<'source> AsHtml for MarkdownText<'source> {
+    fn write_html<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+        self.render_html(output, &HashMap::new(), &RenderOptions::default())
+    }
+}
+
 impl<'source> AsText for MarkdownText<'source> {
     fn write_as_text<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
         match self {
-            MarkdownText::Text(t) => write!(output, "{t}")?,
+            MarkdownText::Text(t, _) => write!(output, "{t}")?,
             MarkdownText::Image(image) => image.write_as_text(output)?,
             MarkdownText::Link(link) => link.write_as_text(output)?,
             MarkdownText::AutoLink(link) => link.write_as_text(output)?,
-            MarkdownText::SoftBreak => writeln!(output)?,
-            MarkdownText::Code { code } => write!(output, "`{code}`")?,
+            MarkdownText::SoftBreak(_) => writeln!(output)?,
+            MarkdownText::Code { code, .. } => write!(output, "`{code}`")?,
         }
         Ok(())
     }
 }
 
+impl<'source> std::fmt::Display for MarkdownText<'source> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_as_text_fmt(f)
+    }
+}
+
 impl<'source> MarkdownText<'source> {
+    pub(crate) fn render_latex<Writer: std::io::Write>(
+        &self,
+        output: &mut Writer,
+        definitions: &HashMap<String, &str>,
+        options: &RenderOptions,
+    ) -> std::io::Result<()> {
+        match self {
+            MarkdownText::Text(text, _) => {
+                let mut stripped = String::new();
+                strip_markup(text, None, &mut stripped);
+                write!(output, "{}", escape_latex(&stripped))?
+            }
+            MarkdownText::Image(image) => image.render_latex(output, definitions)?,
+            MarkdownText::Link(link) => link.render_latex(output, definitions, options)?,
+            MarkdownText::AutoLink(auto_link) => auto_link.write_latex(output)?,
+            MarkdownText::SoftBreak(_) => match options.soft_break {
+                SoftBreak::Newline => writeln!(output)?,
+                SoftBreak::Space => write!(output, " ")?,
+            },
+            MarkdownText::Code { code, .. } => write!(output, "\\texttt{{{}}}", escape_latex(code))?,
+        }
+
+        Ok(())
+    }
+}
+
+impl<'source> AsLatex for MarkdownText<'source> {
+    fn write_latex<Writer: std::io::Write>(&self, output: &mut Writer) -> std::io::Result<()> {
+        self.render_latex(output, &HashMap::new(), &RenderOptions::default())
+    }
+}
+
+/// Write `text` as html.  A line ending in two spaces or a backslash is a hard break, which needs
+/// to look at the soft break after it.
+pub(crate) fn write_html_inlines<Writer: std::io::Write>(
+    text: &[MarkdownText<'_>],
+    output: &mut Writer,
+    definitions: &HashMap<String, &str>,
+    options: &RenderOptions,
+) -> std::io::Result<()> {
+    let mut iter = text.iter().peekable();
+    while let Some(t) = iter.next() {
+        let MarkdownText::Text(s, _) = t else {
+            t.render_html(output, definitions, options)?;
+            continue;
+        };
+        if !matches!(iter.peek(), Some(MarkdownText::SoftBreak(_))) {
+            write_html_text(s, output)?;
+            continue;
+        }
+
+        let Some(line) = hard_break(s) else {
+            write_html_text(s, output)?;
+            continue;
+        };
+        iter.next();
+        write_html_text(line, output)?;
+        match options.xhtml {
+            true => writeln!(output, "<br />")?,
+            false => writeln!(output, "<br>")?,
+        }
+    }
+
+    Ok(())
+}
+
+/// If the line ending after `line` is a hard break, because `line` ends in two spaces or a
+/// backslash, the line without them.
+pub(crate) fn hard_break(line: &str) -> Option<&str> {
+    let backslashes = line.len() - line.trim_end_matches('\\').len();
+    if line.ends_with("  ") {
+        Some(line.trim_end_matches(' '))
+    } else if backslashes % 2 == 1 {
+        Some(&line[..line.len() - 1])
+    } else {
+        None
+    }
+}
+
+/// Write markdown text as html.  Backslash escapes are written as the character they escape, and
+/// a bare `&` is escaped, while anything else, like inline html, is written as it is.
+fn write_html_text<Writer: std::io::Write>(text: &str, output: &mut Writer) -> std::io::Result<()> {
+    let mut rest = text;
+    while let Some(i) = rest.find('\\') {
+        write!(output, "{}", escape_ampersands(&rest[..i]))?;
+        rest = &rest[i + 1..];
+        match rest.chars().next().filter(char::is_ascii_punctuation) {
+            Some(c) => {
+                write!(output, "{}", escape_html(&rest[..1]))?;
+                rest = &rest[c.len_utf8()..];
+            }
+            None => write!(output, "\\")?,
+        }
+    }
+    write!(output, "{}", escape_ampersands(rest))
+}
+
+/// Write `text` as LaTeX.  Emphasis markers at the end of a piece of text are recognized by
+/// looking at the piece that comes after it, and soft breaks by the lines around them, which
+/// writing each piece on its own can't do.
+pub(crate) fn write_latex_text<Writer: std::io::Write>(
+    text: &[MarkdownText<'_>],
+    output: &mut Writer,
+    definitions: &HashMap<String, &str>,
+    options: &RenderOptions,
+) -> std::io::Result<()> {
+    for (i, t) in text.iter().enumerate() {
+        let MarkdownText::Text(s, _) = t else {
+            match t {
+                MarkdownText::SoftBreak(_) => write!(output, "{}", options.soft_break_at(text, i))?,
+                _ => t.render_latex(output, definitions, options)?,
+            }
+            continue;
+        };
+
+        let next = text.get(i + 1).and_then(|next| match next {
+            MarkdownText::Text(s, _) => s.chars().next(),
+            MarkdownText::Image(_) => Some('!'),
+            MarkdownText::Link(_) => Some('['),
+            MarkdownText::AutoLink(_) => Some('<'),
+            MarkdownText::SoftBreak(_) => Some('\n'),
+            MarkdownText::Code { .. } => Some('`'),
+        });
+        let mut stripped = String::new();
+        strip_markup(s, next, &mut stripped);
+        write!(output, "{}", escape_latex(&stripped))?;
+    }
+
+    Ok(())
+}
+
+/// Iterates over inline content depth first, so the text of a link comes right after the link.
+/// Made by [`MarkdownText::walk`].
+#[derive(Debug, Clone)]
+pub struct Inlines<'a, 'source> {
+    stack: Vec<std::slice::Iter<'a, MarkdownText<'source>>>,
+}
+
+impl<'a, 'source> Iterator for Inlines<'a, 'source> {
+    type Item = &'a MarkdownText<'source>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let Some(t) = self.stack.last_mut()?.next() else {
+                self.stack.pop();
+                continue;
+            };
+            if let MarkdownText::Link(link) = t {
+                self.stack.push(link.link_text.iter());
+            }
+            return Some(t);
+        }
+    }
+}
+
+impl<'source> MarkdownText<'source> {
+    /// Every piece of `text`, including the ones inside of links
+    pub fn walk<'a>(text: &'a [MarkdownText<'source>]) -> Inlines<'a, 'source> {
+        Inlines {
+            stack: vec![text.iter()],
+        }
+    }
+
+    /// Text that was parsed from `text`
+    pub(crate) fn text(text: &'source str) -> Self {
+        MarkdownText::Text(text.into(), Span::of(text))
+    }
+
+    /// Copy this so it no longer borrows from the source.
+    pub fn into_owned(self) -> MarkdownText<'static> {
+        match self {
+            MarkdownText::Text(text, span) => {
+                MarkdownText::Text(Cow::Owned(text.into_owned()), span)
+            }
+            MarkdownText::Image(image) => MarkdownText::Image(image.into_owned()),
+            MarkdownText::Link(link) => MarkdownText::Link(link.into_owned()),
+            MarkdownText::AutoLink(link) => MarkdownText::AutoLink(link.into_owned()),
+            MarkdownText::SoftBreak(span) => MarkdownText::SoftBreak(span),
+            MarkdownText::Code { code, span } => MarkdownText::Code {
+                code: Cow::Owned(code.into_owned()),
+                span,
+            },
+        }
+    }
+
+    pub(crate) fn into_owned_all(text: Vec<Self>) -> Vec<MarkdownText<'static>> {
+        text.into_iter().map(MarkdownText::into_owned).collect()
+    }
+
+    /// The part of the source this was parsed from
+    pub fn span(&self) -> Span {
+        match self {
+            MarkdownText::Text(_, span)
+            | MarkdownText::SoftBreak(span)
+            | MarkdownText::Code { span, .. } => *span,
+            MarkdownText::Image(image) => image.span,
+            MarkdownText::Link(link) => link.span,
+            MarkdownText::AutoLink(link) => link.span,
+        }
+    }
+
     pub fn parse_markdown_text_until<F>(
         input: &'source str,
         matcher: F,
@@ -75,7 +308,7 @@ impl<'source> MarkdownText<'source> {
             take_till0(matcher)
                 .recognize()
                 .context("text data")
-                .map(MarkdownText::Text),
+                .map(MarkdownText::text),
         ))
         .context("markdown leaf node")
         .parse_next(input)
@@ -83,7 +316,7 @@ impl<'source> MarkdownText<'source> {
 
     fn take1(input: &'source str) -> IResult<&'source str, Self> {
         take(1usize)
-            .map(MarkdownText::Text)
+            .map(MarkdownText::text)
             .context("take 1 character")
             .parse_next(input)
     }
@@ -106,16 +339,23 @@ impl<'source> MarkdownText<'source> {
                 parse_auto_link.context("auto link").map(MarkdownText::AutoLink),
                 MarkdownText::take1,
             )),
-            "\n" => newline.map(|_| MarkdownText::SoftBreak).context("soft break"),
+            // an escaped character is kept with its backslash, so it's never read as markup
+            "\\" => alt((("\\", none_of("\n")).recognize(), take(1usize)))
+                .map(MarkdownText::text)
+                .context("backslash escape"),
+            "\n" => newline
+                .recognize()
+                .map(|s| MarkdownText::SoftBreak(Span::of(s)))
+                .context("soft break"),
             /*dispatch! {peek(take(2usize));
                 "\n\n" => fail,
                 _ => newline.map(|_| MarkdownText::SoftBreak).context("soft break")
             },*/
             _ => alt((
-                take_till1("\n[]<>!`").map(MarkdownText::Text),
+                take_till1("\n[]<>!`\\").map(MarkdownText::text),
                 terminated(
                     many1(any).map(|_: ()| {}).recognize(),
-                    opt(newline)).map(MarkdownText::Text)
+                    opt(newline)).map(MarkdownText::text)
             )).context("text"),
         };
 
@@ -131,8 +371,22 @@ impl<'source> MarkdownText<'source> {
     }
 }
 
+impl<'source> Locate for MarkdownText<'source> {
+    fn locate(&mut self, source: &str) {
+        match self {
+            MarkdownText::Text(_, span)
+            | MarkdownText::SoftBreak(span)
+            | MarkdownText::Code { span, .. } => span.locate(source),
+            MarkdownText::Image(image) => image.locate(source),
+            MarkdownText::Link(link) => link.locate(source),
+            MarkdownText::AutoLink(link) => link.locate(source),
+        }
+    }
+}
+
 pub fn nested_brackets(input: &str) -> IResult<&str, &str> {
     many0(alt((
+        ("\\", none_of("\n")).context("backslash escape").recognize(),
         none_of("[]").context("non-bracketed text").recognize(),
         delimited("[", nested_brackets, "]")
             .context("bracketed text")
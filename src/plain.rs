@@ -0,0 +1,362 @@
+//! Writing a document as plain prose, without any markup.
+
+use std::{collections::HashMap, io};
+
+use crate::{
+    format::{display_width, render_text},
+    parser::{
+        block::Block,
+        headers::HeadingLevel,
+        images::{Image, ImageRef},
+        links::{normalize_label, Link, LinkRef},
+        util::MarkdownText,
+    },
+    render::{RenderOptions, Renderer},
+    slug::plain_text,
+    toc::TocEntry,
+    Markdown,
+};
+
+/// Settings for writing a [`Markdown`] document as plain text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlainTextOptions {
+    /// Write the target of each link after its text, like `text (url)`
+    pub link_urls: bool,
+}
+
+impl Default for PlainTextOptions {
+    fn default() -> Self {
+        Self { link_urls: true }
+    }
+}
+
+/// A piece of rendered inline text.  Only text that came from markdown source can contain markup;
+/// code and link targets are written as they are.
+struct Piece {
+    text: String,
+    markup: bool,
+}
+
+impl<'source> Markdown<'source> {
+    /// Write this document as plain text.
+    ///
+    /// Headings are underlined, list bullets become `•`, emphasis markers and escapes are dropped,
+    /// and links are written as their text, optionally followed by their target.  Link
+    /// definitions, comments and front matter are left out.
+    pub fn write_plain_text<Writer: io::Write>(
+        &self,
+        output: &mut Writer,
+        options: &PlainTextOptions,
+    ) -> io::Result<()> {
+        self.render(options, output, &RenderOptions::default())
+    }
+
+    /// Write this document as plain text, following the settings every format shares.
+    fn render_plain_text<Writer: io::Write>(
+        &self,
+        output: &mut Writer,
+        options: &PlainTextOptions,
+        render_options: &RenderOptions,
+    ) -> io::Result<()> {
+        let definitions = link_targets(&self.blocks);
+
+        let mut first = true;
+        for block in self.blocks.iter() {
+            let text = match block {
+                Block::Paragraph(p) => inline_text(&p.text, &definitions, options, render_options),
+                Block::Heading(h) => {
+                    let text = inline_text(h.text(), &definitions, options, render_options);
+                    let text = text.trim();
+                    let width = text.lines().map(display_width).max().unwrap_or_default();
+                    match h.level() {
+                        HeadingLevel::H1 => format!("{text}\n{}", "=".repeat(width)),
+                        HeadingLevel::H2 => format!("{text}\n{}", "-".repeat(width)),
+                        _ => text.to_string(),
+                    }
+                }
+                Block::Toc(toc) => {
+                    fn push_entries(entries: &[TocEntry], depth: usize, out: &mut String) {
+                        for entry in entries.iter() {
+                            out.push_str(&format!(
+                                "{:indent$}• {}\n",
+                                "",
+                                entry.text,
+                                indent = depth * 2
+                            ));
+                            push_entries(&entry.children, depth + 1, out);
+                        }
+                    }
+
+                    let mut text = String::new();
+                    push_entries(&toc.entries, 0, &mut text);
+                    text
+                }
+                Block::LinkDefinition(_) | Block::Comment(..) | Block::Separator(..) => continue,
+            };
+            let text = text.trim_matches('\n');
+            if text.is_empty() {
+                continue;
+            }
+
+            if !first {
+                writeln!(output)?;
+            }
+            writeln!(output, "{text}")?;
+            first = false;
+        }
+
+        Ok(())
+    }
+}
+
+impl Renderer for PlainTextOptions {
+    fn render<Writer: io::Write>(
+        &self,
+        md: &Markdown<'_>,
+        output: &mut Writer,
+        options: &RenderOptions,
+    ) -> io::Result<()> {
+        md.render_plain_text(output, self, options)
+    }
+}
+
+/// The target of every link definition in `blocks`, by normalized label.
+pub(crate) fn link_targets<'a>(blocks: &'a [Block<'_>]) -> HashMap<String, &'a str> {
+    let mut definitions = HashMap::new();
+    for block in blocks.iter() {
+        if let Block::LinkDefinition(d) = block {
+            // the first definition of a label wins
+            definitions
+                .entry(normalize_label(&d.label))
+                .or_insert(d.target.as_ref());
+        }
+    }
+
+    definitions
+}
+
+/// Where `link` points to, looking up reference-style links in `definitions`.
+pub(crate) fn link_target<'a>(
+    link: &'a Link<'_>,
+    definitions: &HashMap<String, &'a str>,
+) -> Option<&'a str> {
+    match &link.link_ref {
+        LinkRef::Inline(target) => Some(target),
+        LinkRef::Ref(label) if label.is_empty() => definitions
+            .get(&normalize_label(&render_text(&link.link_text)))
+            .copied(),
+        LinkRef::Ref(label) => definitions.get(&normalize_label(label)).copied(),
+    }
+}
+
+/// Where `image` points to, looking up reference-style images in `definitions`.
+pub(crate) fn image_target<'a>(
+    image: &'a Image<'_>,
+    definitions: &HashMap<String, &'a str>,
+) -> Option<&'a str> {
+    match &image.image_ref {
+        ImageRef::Inline(target) => Some(target),
+        ImageRef::Ref(label) if label.is_empty() => {
+            definitions.get(&normalize_label(&image.alt_text)).copied()
+        }
+        ImageRef::Ref(label) => definitions.get(&normalize_label(label)).copied(),
+    }
+}
+
+/// Render `text` without any markup, with its soft breaks written the way `render_options` asks.
+fn inline_text(
+    text: &[MarkdownText<'_>],
+    definitions: &HashMap<String, &str>,
+    options: &PlainTextOptions,
+    render_options: &RenderOptions,
+) -> String {
+    fn push_pieces(
+        text: &[MarkdownText<'_>],
+        definitions: &HashMap<String, &str>,
+        options: &PlainTextOptions,
+        render_options: &RenderOptions,
+        pieces: &mut Vec<Piece>,
+    ) {
+        let plain = |text: &str| Piece {
+            text: text.to_string(),
+            markup: false,
+        };
+        for (i, t) in text.iter().enumerate() {
+            match t {
+                MarkdownText::Text(s, _) => pieces.push(Piece {
+                    text: s.to_string(),
+                    markup: true,
+                }),
+                MarkdownText::SoftBreak(_) => {
+                    pieces.push(plain(render_options.soft_break_at(text, i)))
+                }
+                MarkdownText::Code { code, .. } => pieces.push(plain(code)),
+                MarkdownText::AutoLink(link) => pieces.push(plain(&link.target)),
+                MarkdownText::Image(image) => {
+                    pieces.push(Piece {
+                        text: image.alt_text.to_string(),
+                        markup: true,
+                    });
+                    if let Some(target) =
+                        image_target(image, definitions).filter(|_| options.link_urls)
+                    {
+                        pieces.push(plain(&format!(" ({target})")));
+                    }
+                }
+                MarkdownText::Link(link) => {
+                    push_pieces(
+                        &link.link_text,
+                        definitions,
+                        options,
+                        render_options,
+                        pieces,
+                    );
+                    let text = plain_text(&link.link_text);
+                    if let Some(target) = link_target(link, definitions)
+                        .filter(|target| options.link_urls && *target != text.trim())
+                    {
+                        pieces.push(plain(&format!(" ({target})")));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut pieces = vec![];
+    push_pieces(text, definitions, options, render_options, &mut pieces);
+
+    let mut out = String::new();
+    for (i, piece) in pieces.iter().enumerate() {
+        if !piece.markup {
+            out.push_str(&piece.text);
+            continue;
+        }
+
+        let next = pieces[i + 1..].iter().find_map(|p| p.text.chars().next());
+        strip_markup(&piece.text, next, &mut out);
+    }
+
+    out.lines()
+        .map(|line| bullet(line.trim_end()))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Turn a markdown list item into one with a `•` bullet, keeping its indentation.
+pub(crate) fn bullet(line: &str) -> String {
+    let content = line.trim_start();
+    let indent = &line[..line.len() - content.len()];
+    for marker in ["- ", "* ", "+ "] {
+        if let Some(item) = content.strip_prefix(marker) {
+            return format!("{indent}• {}", item.trim_start());
+        }
+    }
+
+    line.to_string()
+}
+
+/// Push `text` onto `out` without emphasis markers or backslash escapes.  `next` is the character
+/// that comes after `text`, used to tell whether a marker at the end of `text` closes emphasis.
+///
+/// A run of `*`, `_` or `~~` is dropped when it can open or close emphasis: it touches a word on
+/// one side and whitespace or punctuation on the other.  Underscores inside of words, like in
+/// `snake_case`, are kept.
+pub(crate) fn strip_markup(text: &str, next: Option<char>, out: &mut String) {
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c == '\\' && chars.get(i + 1).is_some_and(char::is_ascii_punctuation) {
+            out.push(chars[i + 1]);
+            i += 2;
+            continue;
+        }
+        if !matches!(c, '*' | '_' | '~') {
+            out.push(c);
+            i += 1;
+            continue;
+        }
+
+        let run = chars[i..].iter().take_while(|&&d| d == c).count();
+        let before = out.chars().next_back();
+        let after = chars.get(i + run).copied().or(next);
+        let is_space = |c: Option<char>| c.is_none_or(char::is_whitespace);
+        let is_punctuation = |c: Option<char>| c.is_some_and(|c| c.is_ascii_punctuation());
+
+        let left_flanking = !is_space(after) && (is_space(before) || is_punctuation(before));
+        let right_flanking = !is_space(before) && (is_space(after) || is_punctuation(after));
+        let intraword = !is_space(before)
+            && !is_space(after)
+            && !is_punctuation(before)
+            && !is_punctuation(after);
+        let is_marker = match c {
+            '~' => run == 2 && (left_flanking || right_flanking),
+            '_' => left_flanking != right_flanking,
+            _ => left_flanking || right_flanking || intraword,
+        };
+
+        if !is_marker {
+            (0..run).for_each(|_| out.push(c));
+        }
+        i += run;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn plain(input: &str, options: &PlainTextOptions) -> String {
+        let md = Markdown::parse(input);
+        let mut output = vec![];
+        md.write_plain_text(&mut output, options).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn document() {
+        let input = "---\ntitle: x\n---\n\n# Getting *started*\n\n<!-- note -->\n\n\
+                     Read the [guide](https://example.com) and [the faq][faq], or see <https://x.y>.\n\
+                     Run `cargo *build*` first.\n\n\
+                     ### Options\n\n\
+                     - **fast** mode\n  * nested\n\n\
+                     [faq]: https://example.com/faq\n";
+        assert_eq!(
+            plain(input, &PlainTextOptions::default()),
+            "Getting started\n===============\n\n\
+             Read the guide (https://example.com) and the faq (https://example.com/faq), or see https://x.y.\n\
+             Run cargo *build* first.\n\n\
+             Options\n\n\
+             • fast mode\n  • nested\n"
+        );
+    }
+
+    #[test]
+    fn links_without_urls() {
+        assert_eq!(
+            plain(
+                "See [docs](https://example.com) and ![a cat](cat.png).",
+                &PlainTextOptions { link_urls: false }
+            ),
+            "See docs and a cat.\n"
+        );
+        assert_eq!(
+            plain(
+                "[https://example.com](https://example.com)",
+                &PlainTextOptions::default()
+            ),
+            "https://example.com\n"
+        );
+    }
+
+    #[test]
+    fn markup() {
+        let mut out = String::new();
+        strip_markup(
+            r"snake_case, _em_, __strong__, ~~gone~~, 2 * 3, a*b*c, \*literal\*",
+            None,
+            &mut out,
+        );
+        assert_eq!(out, "snake_case, em, strong, gone, 2 * 3, abc, *literal*");
+    }
+}
@@ -0,0 +1,259 @@
+//! Writing documents out in any format, with settings that all formats share.
+//!
+//! Each output format is a [`Renderer`].  The built-in formats are implemented by their options
+//! types, like [`HtmlOptions`](crate::HtmlOptions) and [`LatexOptions`](crate::latex::LatexOptions),
+//! plus [`MarkdownRenderer`] for writing markdown back out.  Other formats can be added by
+//! implementing [`Renderer`] on top of [`Markdown::blocks`] or a [`Visitor`](crate::visit::Visitor).
+
+use std::{fmt, io, str::FromStr};
+
+use crate::{
+    parser::util::{hard_break, MarkdownText},
+    write_to_fmt, AsText, Markdown,
+};
+
+/// How a line break inside a paragraph is written.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoftBreak {
+    /// Keep the line break
+    #[default]
+    Newline,
+    /// Join the lines with a space
+    Space,
+}
+
+impl FromStr for SoftBreak {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "newline" => Ok(SoftBreak::Newline),
+            "space" => Ok(SoftBreak::Space),
+            _ => Err(format!("expected `newline` or `space`, found `{s}`")),
+        }
+    }
+}
+
+impl fmt::Display for SoftBreak {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SoftBreak::Newline => write!(f, "newline"),
+            SoftBreak::Space => write!(f, "space"),
+        }
+    }
+}
+
+/// Settings shared by every output format.  Formats ignore the settings that have no meaning for
+/// them: the built-in ones all follow `soft_break` except markdown, which is written as it was
+/// read, and only html uses the rest.
+#[derive(Default, Debug, Clone, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// Close empty elements the way xhtml needs them to be, as in `<br />`
+    pub xhtml: bool,
+    /// How a line break inside a paragraph is written.  Hard breaks are always kept.
+    pub soft_break: SoftBreak,
+    /// Put in front of every heading id, and every link to one, so the ids don't clash with
+    /// others on the page the document ends up in
+    pub heading_id_prefix: String,
+    /// Open links in a new tab, with `target="_blank"`
+    pub links_in_new_tab: bool,
+}
+
+impl RenderOptions {
+    /// What to write for the soft break at `text[i]`.  Hard breaks are kept, and so are breaks
+    /// before a list item, since the text formats start a new item on each of them.
+    pub(crate) fn soft_break_at(&self, text: &[MarkdownText<'_>], i: usize) -> &'static str {
+        let hard = match i.checked_sub(1).map(|before| &text[before]) {
+            Some(MarkdownText::Text(line, _)) => hard_break(line).is_some(),
+            _ => false,
+        };
+        let item = match text.get(i + 1) {
+            Some(MarkdownText::Text(line, _)) => {
+                let line = line.trim_start();
+                ["- ", "* ", "+ "].iter().any(|m| line.starts_with(m))
+            }
+            _ => false,
+        };
+
+        match self.soft_break {
+            SoftBreak::Space if !hard && !item => " ",
+            _ => "\n",
+        }
+    }
+}
+
+/// An output format for documents.
+pub trait Renderer {
+    fn render<Writer: io::Write>(
+        &self,
+        md: &Markdown<'_>,
+        output: &mut Writer,
+        options: &RenderOptions,
+    ) -> io::Result<()>;
+}
+
+/// Writes documents back out as markdown, exactly as [`AsText`] does.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MarkdownRenderer;
+
+impl Renderer for MarkdownRenderer {
+    fn render<Writer: io::Write>(
+        &self,
+        md: &Markdown<'_>,
+        output: &mut Writer,
+        _options: &RenderOptions,
+    ) -> io::Result<()> {
+        md.write_as_text(output)
+    }
+}
+
+impl<'source> Markdown<'source> {
+    /// Write this document with `renderer`.
+    pub fn render<R: Renderer, Writer: io::Write>(
+        &self,
+        renderer: &R,
+        output: &mut Writer,
+        options: &RenderOptions,
+    ) -> io::Result<()> {
+        renderer.render(self, output, options)
+    }
+
+    /// Write this document with `renderer` to a `String`.
+    ///
+    /// # Panics
+    ///
+    /// If `renderer` writes something that isn't UTF-8.
+    pub fn render_to_string<R: Renderer>(&self, renderer: &R, options: &RenderOptions) -> String {
+        let mut output = String::new();
+        write_to_fmt(&mut output, |output| renderer.render(self, output, options))
+            .expect("renderers write UTF-8");
+        output
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ansi::AnsiOptions, latex::LatexOptions, parser::block::Block, plain::PlainTextOptions,
+        roff::RoffOptions, HtmlOptions,
+    };
+
+    fn html(input: &str, options: &RenderOptions) -> String {
+        let mut md = Markdown::parse(input);
+        md.update_toc(&Default::default());
+        let mut output = vec![];
+        md.render(&HtmlOptions::default(), &mut output, options)
+            .unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn breaks() {
+        let input = "one  \ntwo\\\nthree\nfour";
+        assert_eq!(
+            html(input, &RenderOptions::default()),
+            "<p>one<br>\ntwo<br>\nthree\nfour</p>"
+        );
+        assert_eq!(
+            html(
+                input,
+                &RenderOptions {
+                    xhtml: true,
+                    soft_break: SoftBreak::Space,
+                    ..Default::default()
+                }
+            ),
+            "<p>one<br />\ntwo<br />\nthree four</p>"
+        );
+    }
+
+    #[test]
+    fn soft_breaks_in_every_format() {
+        let md = Markdown::parse("one  \ntwo\nthree [a\nb](/x)\n* four\nfive");
+        let options = RenderOptions {
+            soft_break: SoftBreak::Space,
+            ..Default::default()
+        };
+        let plain = PlainTextOptions {
+            link_urls: false,
+        };
+        assert_eq!(
+            md.render_to_string(&plain, &options),
+            "one\ntwo three a b\n• four five\n"
+        );
+        assert_eq!(
+            md.render_to_string(&plain, &RenderOptions::default()),
+            "one\ntwo\nthree a\nb\n• four\nfive\n"
+        );
+        assert_eq!(
+            md.render_to_string(&LatexOptions::default(), &options),
+            "one  \ntwo three \\href{/x}{a b}\n* four five"
+        );
+        assert_eq!(
+            md.render_to_string(&RoffOptions::default(), &options),
+            ".PP\none\ntwo three a b \\(la/x\\(ra\n.IP \\(bu 2\nfour five\n"
+        );
+        let ansi = AnsiOptions {
+            width: None,
+            color: false,
+        };
+        assert_eq!(
+            md.render_to_string(&ansi, &options),
+            "one\ntwo three a b (/x)\n• four five\n"
+        );
+        assert_eq!(
+            md.render_to_string(&ansi, &RenderOptions::default()),
+            "one\ntwo\nthree a\nb (/x)\n• four\n  five\n"
+        );
+    }
+
+    #[test]
+    fn ids_and_links() {
+        let options = RenderOptions {
+            heading_id_prefix: "doc-".to_string(),
+            links_in_new_tab: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            html(
+                "<!-- toc -->\n<!-- tocstop -->\n\n# Intro\n\nSee [this](/x), [that][y] or \
+                 <https://y.z>.\n\n[y]: /y",
+                &options
+            ),
+            "<!-- toc -->\n<nav class=\"toc\"><ul><li><a href=\"#doc-intro\">Intro</a></li></ul></nav>\
+             \n<!-- tocstop -->\n<h1 id=\"doc-intro\">Intro</h1>\n<p>See \
+             <a href=\"/x\" target=\"_blank\" rel=\"noopener\">this</a>, \
+             <a href=\"/y\" target=\"_blank\" rel=\"noopener\">that</a> or \
+             <a href=\"https://y.z\" target=\"_blank\" rel=\"noopener\">https://y.z</a>.</p>\n"
+        );
+    }
+
+    #[test]
+    fn custom_renderer() {
+        /// Writes the text of each heading on its own line.
+        struct Outline;
+
+        impl Renderer for Outline {
+            fn render<Writer: io::Write>(
+                &self,
+                md: &Markdown<'_>,
+                output: &mut Writer,
+                _options: &RenderOptions,
+            ) -> io::Result<()> {
+                for block in md.blocks() {
+                    if let Block::Heading(h) = block {
+                        writeln!(output, "{}", h.plain_text())?;
+                    }
+                }
+                Ok(())
+            }
+        }
+
+        let md = Markdown::parse("# One\n\ntext\n\n## Two `2`\n");
+        assert_eq!(
+            md.render_to_string(&Outline, &RenderOptions::default()),
+            "One\nTwo 2\n"
+        );
+    }
+}
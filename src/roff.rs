@@ -0,0 +1,267 @@
+//! Writing a document as a man page, using roff and the `man` macros.
+
+use std::{collections::HashMap, io};
+
+use crate::{
+    format::indented_code,
+    parser::{block::Block, headers::HeadingLevel, util::MarkdownText},
+    plain::{image_target, link_target, link_targets, strip_markup},
+    render::{RenderOptions, Renderer},
+    slug::plain_text,
+    Markdown,
+};
+
+/// Settings for writing a [`Markdown`] document as a man page.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoffOptions {
+    /// The manual section, used when the title doesn't name one like `md2md(1)` does
+    pub section: String,
+    /// The date to put in the page footer
+    pub date: Option<String>,
+}
+
+impl Default for RoffOptions {
+    fn default() -> Self {
+        Self {
+            section: "1".to_string(),
+            date: None,
+        }
+    }
+}
+
+/// Escape `text` so roff writes it as it is.  A bare `-` would be typeset as a hyphen, which
+/// breaks copying options and commands out of the page.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e").replace('-', "\\-")
+}
+
+/// Quote `text` for use as an argument to a macro.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", escape(text).replace('"', "\\(dq"))
+}
+
+/// Keep lines that start with a `.` or `'` from being read as requests.
+fn escape_line(line: &str) -> String {
+    if line.starts_with(['.', '\'']) {
+        format!("\\&{line}")
+    } else {
+        line.to_string()
+    }
+}
+
+impl<'source> Markdown<'source> {
+    /// Write this document as a man page.
+    ///
+    /// The first H1 becomes the `.TH` title line; if it looks like `name(section)`, the section is
+    /// taken from it.  Other headings become `.SH` and `.SS`, paragraphs become `.PP`, list items
+    /// become `.IP` and paragraphs indented by four spaces are written as code, between `.nf` and
+    /// `.fi`.
+    pub fn write_roff<Writer: io::Write>(
+        &self,
+        output: &mut Writer,
+        options: &RoffOptions,
+    ) -> io::Result<()> {
+        self.render(options, output, &RenderOptions::default())
+    }
+
+    /// Write this document as a man page, following the settings every format shares.
+    fn render_roff<Writer: io::Write>(
+        &self,
+        output: &mut Writer,
+        options: &RoffOptions,
+        render_options: &RenderOptions,
+    ) -> io::Result<()> {
+        let definitions = link_targets(&self.blocks);
+
+        let mut has_title = false;
+        for block in self.blocks.iter() {
+            match block {
+                Block::Heading(h) if h.level() == HeadingLevel::H1 && !has_title => {
+                    let title = plain_text(h.text());
+                    let title = title.trim();
+                    let (name, section) =
+                        match title.strip_suffix(')').and_then(|t| t.split_once('(')) {
+                            Some((name, section)) if !name.contains(' ') => (name, section),
+                            _ => (title, options.section.as_str()),
+                        };
+                    write!(
+                        output,
+                        ".TH {} {}",
+                        quote(&name.to_uppercase()),
+                        quote(section)
+                    )?;
+                    if let Some(date) = &options.date {
+                        write!(output, " {}", quote(date))?;
+                    }
+                    writeln!(output)?;
+                    has_title = true;
+                }
+                Block::Heading(h) => {
+                    let request = match h.level() {
+                        HeadingLevel::H1 | HeadingLevel::H2 => ".SH",
+                        _ => ".SS",
+                    };
+                    let text = plain_text(h.text()).replace('\n', " ");
+                    writeln!(output, "{request} {}", quote(text.trim()))?;
+                }
+                Block::Paragraph(p) => {
+                    if let Some(lines) = indented_code(&p.text) {
+                        writeln!(output, ".PP\n.nf")?;
+                        for line in lines.iter() {
+                            writeln!(output, "{}", escape_line(&escape(line)))?;
+                        }
+                        writeln!(output, ".fi")?;
+                        continue;
+                    }
+
+                    let mut in_paragraph = false;
+                    for line in inline_roff(&p.text, &definitions, render_options).lines() {
+                        let line = line.trim();
+                        if line.is_empty() {
+                            continue;
+                        }
+
+                        // the text is already escaped, so a `-` bullet is `\-` by now
+                        let item = ["\\- ", "* ", "+ "]
+                            .iter()
+                            .find_map(|marker| line.strip_prefix(marker));
+                        match item {
+                            Some(item) => {
+                                writeln!(output, ".IP \\(bu 2")?;
+                                writeln!(output, "{}", escape_line(item.trim_start()))?;
+                            }
+                            None => {
+                                if !in_paragraph {
+                                    writeln!(output, ".PP")?;
+                                }
+                                writeln!(output, "{}", escape_line(line))?;
+                            }
+                        }
+                        in_paragraph = item.is_none();
+                    }
+                }
+                Block::Toc(_)
+                | Block::LinkDefinition(_)
+                | Block::Comment(..)
+                | Block::Separator(..) => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Renderer for RoffOptions {
+    fn render<Writer: io::Write>(
+        &self,
+        md: &Markdown<'_>,
+        output: &mut Writer,
+        options: &RenderOptions,
+    ) -> io::Result<()> {
+        md.render_roff(output, self, options)
+    }
+}
+
+/// Render `text` as roff, with code in bold and links followed by their target.  Lines aren't
+/// escaped yet, since that depends on where they end up.
+fn inline_roff(
+    text: &[MarkdownText<'_>],
+    definitions: &HashMap<String, &str>,
+    render_options: &RenderOptions,
+) -> String {
+    /// Pieces of roff, and whether each one is markdown text that still has markup in it
+    fn push_pieces(
+        text: &[MarkdownText<'_>],
+        definitions: &HashMap<String, &str>,
+        render_options: &RenderOptions,
+        pieces: &mut Vec<(String, bool)>,
+    ) {
+        for (i, t) in text.iter().enumerate() {
+            match t {
+                MarkdownText::Text(s, _) => pieces.push((s.to_string(), true)),
+                MarkdownText::SoftBreak(_) => {
+                    pieces.push((render_options.soft_break_at(text, i).to_string(), false))
+                }
+                MarkdownText::Code { code, .. } => {
+                    pieces.push((format!("\\fB{}\\fR", escape(code)), false))
+                }
+                MarkdownText::AutoLink(link) => pieces.push((escape(&link.target), false)),
+                MarkdownText::Image(image) => {
+                    pieces.push((image.alt_text.to_string(), true));
+                    if let Some(target) = image_target(image, definitions) {
+                        pieces.push((format!(" \\(la{}\\(ra", escape(target)), false));
+                    }
+                }
+                MarkdownText::Link(link) => {
+                    push_pieces(&link.link_text, definitions, render_options, pieces);
+                    let text = plain_text(&link.link_text);
+                    if let Some(target) =
+                        link_target(link, definitions).filter(|target| *target != text.trim())
+                    {
+                        pieces.push((format!(" \\(la{}\\(ra", escape(target)), false));
+                    }
+                }
+            }
+        }
+    }
+
+    let mut pieces = vec![];
+    push_pieces(text, definitions, render_options, &mut pieces);
+
+    let mut out = String::new();
+    for (i, (piece, markup)) in pieces.iter().enumerate() {
+        if !markup {
+            out.push_str(piece);
+            continue;
+        }
+
+        let next = pieces[i + 1..].iter().find_map(|(p, _)| p.chars().next());
+        let mut text = String::new();
+        strip_markup(piece, next, &mut text);
+        out.push_str(&escape(&text));
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn roff(input: &str) -> String {
+        let md = Markdown::parse(input);
+        let mut output = vec![];
+        md.write_roff(&mut output, &RoffOptions::default()).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn man_page() {
+        let input = "# md2md(1)\n\n## Name\n\nmd2md \\- format *markdown*\n\n\
+                     ## Options\n\n### Output\n\n\
+                     Use `-o` to pick a file, see [the docs](https://x.y).\n\
+                     .not a request\n\n\
+                     - one\n  * 'two'\n\n    md2md -i in.md\n    .hidden\n";
+        assert_eq!(
+            roff(input),
+            ".TH \"MD2MD\" \"1\"\n\
+             .SH \"Name\"\n\
+             .PP\nmd2md \\- format markdown\n\
+             .SH \"Options\"\n\
+             .SS \"Output\"\n\
+             .PP\nUse \\fB\\-o\\fR to pick a file, see the docs \\(lahttps://x.y\\(ra.\n\
+             \\&.not a request\n\
+             .IP \\(bu 2\none\n\
+             .IP \\(bu 2\n\\&'two'\n\
+             .PP\n.nf\nmd2md \\-i in.md\n\\&.hidden\n.fi\n"
+        );
+    }
+
+    #[test]
+    fn title_without_section() {
+        assert_eq!(
+            roff("# My tool\n\nText \\ with a backslash"),
+            ".TH \"MY TOOL\" \"1\"\n.PP\nText \\e with a backslash\n"
+        );
+    }
+}
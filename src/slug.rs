@@ -0,0 +1,168 @@
+//! Anchor ids for headings, generated the same way GitHub does.
+
+use std::collections::HashMap;
+
+use crate::{
+    parser::{block::Block, headers::HeadingLevel, util::MarkdownText},
+    Markdown,
+};
+
+/// Generates unique slugs for a document.  Slugs are deduplicated by appending `-1`, `-2`, and so
+/// on, so a single slugger should be used per document.
+#[derive(Default, Debug)]
+pub struct Slugger {
+    occurrences: HashMap<String, usize>,
+}
+
+impl Slugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Generate a slug for `text` that hasn't been handed out by this slugger before.
+    pub fn slug(&mut self, text: &str) -> String {
+        let original = slugify(text);
+        let mut slug = original.clone();
+        while self.occurrences.contains_key(&slug) {
+            let count = self
+                .occurrences
+                .get_mut(&original)
+                .expect("the original slug is always recorded first");
+            *count += 1;
+            slug = format!("{original}-{count}");
+        }
+        self.occurrences.insert(slug.clone(), 0);
+
+        slug
+    }
+}
+
+/// Turn `text` into a slug: lowercase it, strip punctuation and turn each space into a hyphen.
+/// Unlike [`Slugger::slug`], this doesn't make any attempt at uniqueness.
+pub fn slugify(text: &str) -> String {
+    text.trim()
+        .chars()
+        .flat_map(char::to_lowercase)
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The text a reader sees for some inline content, without any markup.
+pub(crate) fn plain_text(text: &[MarkdownText<'_>]) -> String {
+    fn push_text(text: &[MarkdownText<'_>], out: &mut String) {
+        for t in text.iter() {
+            match t {
+                MarkdownText::Text(s, _) => out.push_str(s),
+                MarkdownText::Image(image) => out.push_str(&image.alt_text),
+                MarkdownText::Link(link) => push_text(&link.link_text, out),
+                MarkdownText::AutoLink(link) => out.push_str(&link.target),
+                MarkdownText::SoftBreak(_) => out.push(' '),
+                MarkdownText::Code { code, .. } => out.push_str(code),
+            }
+        }
+    }
+
+    let mut out = String::new();
+    push_text(text, &mut out);
+    out
+}
+
+/// A heading in a document, along with the id used to link to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeadingAnchor {
+    pub level: HeadingLevel,
+    /// The text of the heading, without any markup
+    pub text: String,
+    pub id: String,
+}
+
+impl<'source> Markdown<'source> {
+    /// Every heading in this document, in order, with a unique id for each.  These are the ids
+    /// used when writing html.
+    pub fn heading_anchors(&self) -> Vec<HeadingAnchor> {
+        let mut slugger = Slugger::new();
+        self.blocks
+            .iter()
+            .filter_map(|block| match block {
+                Block::Heading(header) => {
+                    let text = plain_text(header.text());
+                    Some(HeadingAnchor {
+                        level: header.level(),
+                        id: slugger.slug(&text),
+                        text: text.trim().to_string(),
+                    })
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn github_slugs() {
+        assert_eq!(slugify("Hello, World!"), "hello-world");
+        assert_eq!(slugify("API v2 -- notes"), "api-v2----notes");
+        assert_eq!(slugify("snake_case & C++"), "snake_case--c");
+        assert_eq!(slugify("Über Straße"), "über-straße");
+        assert_eq!(slugify("🎉 Release"), "-release");
+    }
+
+    #[test]
+    fn duplicate_slugs() {
+        let mut slugger = Slugger::new();
+        assert_eq!(slugger.slug("Foo"), "foo");
+        assert_eq!(slugger.slug("Foo"), "foo-1");
+        assert_eq!(slugger.slug("foo-1"), "foo-1-1");
+        assert_eq!(slugger.slug("Foo"), "foo-2");
+    }
+
+    #[test]
+    fn anchors() {
+        let md = Markdown::parse("# Intro\n\n## Using `md2md`\n\n## Intro\n");
+        let ids: Vec<_> = md
+            .heading_anchors()
+            .into_iter()
+            .map(|anchor| (anchor.level, anchor.text, anchor.id))
+            .collect();
+        assert_eq!(
+            ids,
+            [
+                (HeadingLevel::H1, "Intro".to_string(), "intro".to_string()),
+                (
+                    HeadingLevel::H2,
+                    "Using md2md".to_string(),
+                    "using-md2md".to_string()
+                ),
+                (HeadingLevel::H2, "Intro".to_string(), "intro-1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn html_ids() {
+        let md = Markdown::parse("# Title\n\n# Title");
+        let mut output = vec![];
+        md.write_html_with(
+            &mut output,
+            &crate::HtmlOptions {
+                heading_self_links: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "<h1 id=\"title\"><a class=\"anchor\" href=\"#title\" aria-hidden=\"true\">#</a>Title</h1>\n\
+             <h1 id=\"title-1\"><a class=\"anchor\" href=\"#title-1\" aria-hidden=\"true\">#</a>Title</h1>"
+        );
+    }
+}
@@ -0,0 +1,278 @@
+//! Where things are in the source of a document.
+
+use std::{fmt, ops::Range};
+
+use serde::{Deserialize, Serialize};
+
+/// A byte range in the source of a document.
+///
+/// Nodes that weren't parsed, like the ones formatting adds, have an empty span at the start of
+/// the document.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub const fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// This span as a range, for slicing the source with
+    pub fn range(&self) -> Range<usize> {
+        self.start..self.end
+    }
+
+    /// Where `text` is in memory.  Parsers only see part of the source, so this is what they
+    /// record; [`crate::Markdown::parse`] then makes it relative to the start of the source.
+    pub(crate) fn of(text: &str) -> Self {
+        let start = text.as_ptr() as usize;
+        Self {
+            start,
+            end: start + text.len(),
+        }
+    }
+
+    /// The span from the start of `input` up to `rest`, which is what's left of it after parsing.
+    pub(crate) fn between(input: &str, rest: &str) -> Self {
+        debug_assert!(
+            within(input, rest) && rest.len() <= input.len(),
+            "the rest of the input isn't part of it"
+        );
+        Self::of(&input[..input.len() - rest.len()])
+    }
+
+    /// The part of this span that `part` takes up, where `part` is a slice of `text`, the text
+    /// this span covers.
+    pub(crate) fn slice(&self, text: &str, part: &str) -> Self {
+        debug_assert!(within(text, part), "the part isn't a slice of the text");
+        let start = self.start + (part.as_ptr() as usize - text.as_ptr() as usize);
+        Self {
+            start,
+            end: start + part.len(),
+        }
+    }
+}
+
+/// Whether `part` is a slice of `text`.
+fn within(text: &str, part: &str) -> bool {
+    let range = text.as_bytes().as_ptr_range();
+    let part = part.as_bytes().as_ptr_range();
+    range.start <= part.start && part.end <= range.end
+}
+
+/// Nodes that record their spans while being parsed.
+pub(crate) trait Locate {
+    /// Turn spans from [`Span::of`] into offsets from the start of `source`.
+    fn locate(&mut self, source: &str);
+}
+
+impl Locate for Span {
+    /// Spans that were never recorded are left empty at the start of the document.  A span of
+    /// anything that isn't part of `source` is a bug, and panics rather than wrapping around.
+    fn locate(&mut self, source: &str) {
+        if *self == Span::default() {
+            return;
+        }
+
+        let base = source.as_ptr() as usize;
+        let offset = |address: usize| {
+            address
+                .checked_sub(base)
+                .filter(|&offset| offset <= source.len())
+                .expect("spans are only recorded from slices of the source")
+        };
+        self.start = offset(self.start);
+        self.end = offset(self.end);
+    }
+}
+
+impl<T: Locate> Locate for Vec<T> {
+    fn locate(&mut self, source: &str) {
+        self.iter_mut().for_each(|t| t.locate(source));
+    }
+}
+
+/// A line and column in a document, both starting from 1.  Columns count characters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Turns byte offsets into the text a document was parsed from into lines and columns.
+///
+/// When that text came from [`crate::cleanup`], positions are reported against the text that was
+/// cleaned up, since that's the one people have open in their editors.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineIndex {
+    /// The text positions are reported against
+    original: String,
+    /// The offset that each line of `original` starts at
+    line_starts: Vec<usize>,
+    /// Offsets in the parsed text, and how many bytes of `original` were dropped before each one
+    dropped: Vec<(usize, usize)>,
+}
+
+impl LineIndex {
+    /// An index for a document that was parsed straight from `text`.
+    pub fn new(text: &str) -> Self {
+        Self::with_cleanup(text, text)
+    }
+
+    /// An index for a document parsed from `cleaned`, which [`crate::cleanup`] made from
+    /// `original`.
+    pub fn with_cleanup(original: &str, cleaned: &str) -> Self {
+        // a `\r` on its own ends a line too, since cleaning up turns it into a `\n`
+        let line_starts = std::iter::once(0)
+            .chain(original.match_indices(['\n', '\r']).filter_map(|(i, end)| {
+                (end == "\n" || original.as_bytes().get(i + 1) != Some(&b'\n')).then_some(i + 1)
+            }))
+            .collect();
+
+        // cleaning up only ever drops characters or turns a `\r` into a `\n`, so the two can be
+        // lined up by skipping over whatever doesn't match
+        let mut dropped: Vec<(usize, usize)> = vec![];
+        let mut total = 0;
+        let mut cleaned_chars = cleaned.char_indices().peekable();
+        let mut original_chars = original.chars().peekable();
+        while let Some(o) = original_chars.next() {
+            let matches = match cleaned_chars.peek() {
+                Some(&(_, c)) => {
+                    c == o || (o == '\r' && c == '\n' && original_chars.peek() != Some(&'\n'))
+                }
+                None => false,
+            };
+            if matches {
+                cleaned_chars.next();
+                continue;
+            }
+
+            total += o.len_utf8();
+            let at = cleaned_chars.peek().map_or(cleaned.len(), |&(i, _)| i);
+            match dropped.last_mut() {
+                Some((last, count)) if *last == at => *count = total,
+                _ => dropped.push((at, total)),
+            }
+        }
+
+        Self {
+            original: original.to_string(),
+            line_starts,
+            dropped,
+        }
+    }
+
+    /// Where `offset` in the parsed text is in the original text.
+    pub fn original_offset(&self, offset: usize) -> usize {
+        let i = self.dropped.partition_point(|&(at, _)| at <= offset);
+        offset + i.checked_sub(1).map_or(0, |i| self.dropped[i].1)
+    }
+
+    /// Where `span` of the parsed text is in the original text.  Characters that were dropped
+    /// right after the span aren't included in it.
+    pub fn original_span(&self, span: Span) -> Span {
+        let i = self.dropped.partition_point(|&(at, _)| at < span.end);
+        let end = span.end + i.checked_sub(1).map_or(0, |i| self.dropped[i].1);
+        let start = self.original_offset(span.start);
+        Span::new(start, end.max(start))
+    }
+
+    /// The line and column of `offset` in the parsed text.
+    pub fn position(&self, offset: usize) -> Position {
+        let offset = self.original_offset(offset).min(self.original.len());
+        let line = self.line_starts.partition_point(|&start| start <= offset) - 1;
+        let start = self.line_starts[line];
+        let column = self
+            .original
+            .get(start..offset)
+            .map_or(offset - start, |text| text.chars().count());
+
+        Position {
+            line: line + 1,
+            column: column + 1,
+        }
+    }
+
+    /// The text of a line in the original text, without its line ending.  Lines start from 1.
+    pub fn line(&self, line: usize) -> Option<&str> {
+        let start = *self.line_starts.get(line.checked_sub(1)?)?;
+        let end = self
+            .line_starts
+            .get(line)
+            .map_or(self.original.len(), |&next| next - 1);
+        let text = &self.original[start..end];
+        Some(text.strip_suffix('\r').unwrap_or(text))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn positions() {
+        let index = LineIndex::new("ab\nçd\n\nx");
+        assert_eq!(index.position(0), Position { line: 1, column: 1 });
+        assert_eq!(index.position(2), Position { line: 1, column: 3 });
+        assert_eq!(index.position(3), Position { line: 2, column: 1 });
+        assert_eq!(index.position(5), Position { line: 2, column: 2 });
+        assert_eq!(index.position(8), Position { line: 4, column: 1 });
+        assert_eq!(index.position(100), Position { line: 4, column: 2 });
+        assert_eq!(index.line(2), Some("çd"));
+        assert_eq!(index.line(3), Some(""));
+        assert_eq!(index.line(5), None);
+    }
+
+    #[test]
+    fn cleaned_up() {
+        let original = "a\r\nb\x1A\rc\r\n";
+        let cleaned = crate::cleanup(original, 4);
+        assert_eq!(cleaned, "a\nb\nc\n");
+
+        let index = LineIndex::with_cleanup(original, &cleaned);
+        assert_eq!(index.original_offset(2), 3);
+        assert_eq!(index.original_offset(4), 6);
+        assert_eq!(index.position(4), Position { line: 3, column: 1 });
+        assert_eq!(index.original_span(Span::new(4, 5)), Span::new(6, 7));
+        assert_eq!(index.original_span(Span::new(0, 1)), Span::new(0, 1));
+        assert_eq!(index.line(1), Some("a"));
+        assert_eq!(index.line(2), Some("b\x1A"));
+    }
+
+    #[test]
+    fn document_positions() {
+        use crate::{parser::block::Block, Markdown};
+
+        let original = "# Title\r\n\r\nSome `code`.\r\n";
+        let cleaned = crate::cleanup(original, 4);
+        let md = Markdown::parse_cleaned(&cleaned, original);
+
+        let Block::Paragraph(p) = &md.blocks[2] else {
+            panic!("expected a paragraph, found {:?}", md.blocks[2]);
+        };
+        let code = p.text[1].span();
+        assert_eq!(&cleaned[code.range()], "`code`");
+        assert_eq!(md.line_index().original_span(code), Span::new(16, 22));
+        assert_eq!(
+            md.line_index().position(code.start),
+            Position { line: 3, column: 6 }
+        );
+        assert_eq!(md.blocks[0].span(), Span::new(0, 7));
+    }
+}
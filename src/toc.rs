@@ -0,0 +1,308 @@
+//! Tables of contents built from the headings in a document.
+
+use std::{fmt, io};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    escape_html,
+    parser::{block::Block, comments::comment_text, headers::HeadingLevel},
+    render::RenderOptions,
+    slug::HeadingAnchor,
+    span::Span,
+    AsHtml, AsLatex, AsText, Markdown,
+};
+
+/// The comment that marks the start of a table of contents in a markdown document.
+pub const TOC_START_MARKER: &str = "toc";
+/// The comment that marks the end of a table of contents in a markdown document.
+pub const TOC_END_MARKER: &str = "tocstop";
+
+/// Which headings make it into a table of contents.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TocOptions {
+    /// Headings above this level (e.g. the document title) are left out
+    pub min_level: HeadingLevel,
+    /// Headings below this level are left out
+    pub max_level: HeadingLevel,
+}
+
+impl Default for TocOptions {
+    fn default() -> Self {
+        Self {
+            min_level: HeadingLevel::H1,
+            max_level: HeadingLevel::H6,
+        }
+    }
+}
+
+/// A single heading in a table of contents, along with the headings nested under it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TocEntry {
+    pub level: HeadingLevel,
+    pub text: String,
+    /// The id of the heading, to link to
+    pub id: String,
+    pub children: Vec<TocEntry>,
+}
+
+/// A table of contents.
+///
+/// As markdown, this is a nested list of links to each heading.  As html, it's a `<nav>` with
+/// nested `<ul>`s.
+#[derive(Default, Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Toc {
+    pub entries: Vec<TocEntry>,
+}
+
+impl Toc {
+    /// Build a table of contents out of `anchors`, nesting each heading under the closest
+    /// preceding heading with a higher level.
+    pub fn from_anchors(anchors: Vec<HeadingAnchor>, options: &TocOptions) -> Self {
+        fn insert(entries: &mut Vec<TocEntry>, entry: TocEntry) {
+            match entries.last_mut() {
+                Some(last) if last.level < entry.level => insert(&mut last.children, entry),
+                _ => entries.push(entry),
+            }
+        }
+
+        let mut toc = Toc::default();
+        for anchor in anchors
+            .into_iter()
+            .filter(|a| a.level >= options.min_level && a.level <= options.max_level)
+        {
+            insert(
+                &mut toc.entries,
+                TocEntry {
+                    level: anchor.level,
+                    text: anchor.text,
+                    id: anchor.id,
+                    children: vec![],
+                },
+            );
+        }
+
+        toc
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl AsText for Toc {
+    fn write_as_text<Writer: io::Write>(&self, output: &mut Writer) -> io::Result<()> {
+        fn write_entries<Writer: io::Write>(
+            entries: &[TocEntry],
+            depth: usize,
+            output: &mut Writer,
+        ) -> io::Result<()> {
+            for entry in entries.iter() {
+                let text = entry.text.replace('[', "\\[").replace(']', "\\]");
+                writeln!(
+                    output,
+                    "{:indent$}- [{text}](#{})",
+                    "",
+                    entry.id,
+                    indent = depth * 2
+                )?;
+                write_entries(&entry.children, depth + 1, output)?;
+            }
+            Ok(())
+        }
+
+        write_entries(&self.entries, 0, output)
+    }
+}
+
+impl fmt::Display for Toc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write_as_text_fmt(f)
+    }
+}
+
+impl Toc {
+    /// Write this table of contents as html, linking to the heading ids with the prefix from
+    /// `options`.
+    pub(crate) fn render_html<Writer: io::Write>(
+        &self,
+        output: &mut Writer,
+        options: &RenderOptions,
+    ) -> io::Result<()> {
+        fn write_entries<Writer: io::Write>(
+            entries: &[TocEntry],
+            prefix: &str,
+            output: &mut Writer,
+        ) -> io::Result<()> {
+            write!(output, "<ul>")?;
+            for entry in entries.iter() {
+                write!(
+                    output,
+                    "<li><a href=\"#{prefix}{}\">{}</a>",
+                    entry.id,
+                    escape_html(&entry.text)
+                )?;
+                if !entry.children.is_empty() {
+                    write_entries(&entry.children, prefix, output)?;
+                }
+                write!(output, "</li>")?;
+            }
+            write!(output, "</ul>")
+        }
+
+        write!(output, "<nav class=\"toc\">")?;
+        if !self.is_empty() {
+            write_entries(&self.entries, &options.heading_id_prefix, output)?;
+        }
+        write!(output, "</nav>")
+    }
+}
+
+impl AsHtml for Toc {
+    fn write_html<Writer: io::Write>(&self, output: &mut Writer) -> io::Result<()> {
+        self.render_html(output, &RenderOptions::default())
+    }
+}
+
+impl AsLatex for Toc {
+    fn write_latex<Writer: io::Write>(&self, output: &mut Writer) -> io::Result<()> {
+        // LaTeX builds its own table of contents out of the document's sections
+        write!(output, "\\tableofcontents")
+    }
+}
+
+impl<'source> Markdown<'source> {
+    /// Build a table of contents for this document.
+    pub fn toc(&self, options: &TocOptions) -> Toc {
+        Toc::from_anchors(self.heading_anchors(), options)
+    }
+
+    /// Regenerate the table of contents between a `<!-- toc -->` and `<!-- tocstop -->` comment,
+    /// replacing whatever was there before.  If there's no end marker, one is added right after
+    /// the new table of contents.
+    ///
+    /// Returns `false` if the document doesn't have a `<!-- toc -->` marker.
+    pub fn update_toc(&mut self, options: &TocOptions) -> bool {
+        fn is_marker(block: &Block<'_>, marker: &str) -> bool {
+            matches!(block, Block::Comment(c, _) if comment_text(c) == marker)
+        }
+
+        let Some(start) = self
+            .blocks
+            .iter()
+            .position(|b| is_marker(b, TOC_START_MARKER))
+        else {
+            return false;
+        };
+        let end = self.blocks[start + 1..]
+            .iter()
+            .position(|b| is_marker(b, TOC_END_MARKER))
+            .map(|end| start + 1 + end);
+
+        let toc = self.toc(options);
+        let mut region = vec![];
+        if !toc.is_empty() {
+            region.push(Block::Separator(2, Span::default()));
+            region.push(Block::Toc(toc));
+        }
+        region.push(Block::Separator(1, Span::default()));
+
+        match end {
+            Some(end) => {
+                self.blocks.splice(start + 1..end, region);
+            }
+            None => {
+                region.push(Block::Comment("<!-- tocstop -->".into(), Span::default()));
+                self.blocks.splice(start + 1..start + 1, region);
+            }
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DOCUMENT: &str = "# Title\n\n## One\n\n### One & a half\n\n## Two\n\n#### Deep\n";
+
+    fn render_text<T: AsText>(t: &T) -> String {
+        let mut output = vec![];
+        t.write_as_text(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn nesting() {
+        let toc = Markdown::parse(DOCUMENT)
+            .toc(&TocOptions::default());
+        assert_eq!(
+            render_text(&toc),
+            "- [Title](#title)\n  \
+               - [One](#one)\n    \
+                 - [One & a half](#one--a-half)\n  \
+               - [Two](#two)\n    \
+                 - [Deep](#deep)\n"
+        );
+    }
+
+    #[test]
+    fn levels() {
+        let toc = Markdown::parse(DOCUMENT).toc(&TocOptions {
+            min_level: HeadingLevel::H2,
+            max_level: HeadingLevel::H3,
+        });
+        assert_eq!(
+            toc.entries
+                .iter()
+                .map(|e| (e.text.as_str(), e.children.len()))
+                .collect::<Vec<_>>(),
+            [("One", 1), ("Two", 0)]
+        );
+    }
+
+    #[test]
+    fn html() {
+        let toc = Markdown::parse("## A & B\n\n### C")
+            .toc(&TocOptions::default());
+        let mut output = vec![];
+        toc.write_html(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "<nav class=\"toc\"><ul><li><a href=\"#a--b\">A &amp; B</a>\
+             <ul><li><a href=\"#c\">C</a></li></ul></li></ul></nav>"
+        );
+    }
+
+    #[test]
+    fn update_in_place() {
+        let mut md = Markdown::parse(
+            "# Title\n\n<!-- toc -->\n\n- [Stale](#stale)\n\n<!-- tocstop -->\n\n## Usage\n",
+        );
+        assert!(md.update_toc(&TocOptions {
+            min_level: HeadingLevel::H2,
+            ..Default::default()
+        }));
+        assert_eq!(
+            render_text(&md),
+            "# Title\n\n<!-- toc -->\n\n- [Usage](#usage)\n\n<!-- tocstop -->\n\n## Usage\n"
+        );
+    }
+
+    #[test]
+    fn update_adds_end_marker() {
+        let mut md = Markdown::parse("<!-- toc -->\n\n# Usage");
+        assert!(md.update_toc(&TocOptions::default()));
+        assert_eq!(
+            render_text(&md),
+            "<!-- toc -->\n\n- [Usage](#usage)\n\n<!-- tocstop -->\n\n# Usage"
+        );
+    }
+
+    #[test]
+    fn no_marker() {
+        let mut md = Markdown::parse("# Usage");
+        assert!(!md.update_toc(&TocOptions::default()));
+    }
+}
@@ -0,0 +1,238 @@
+//! Walking over a document's blocks and inline content.
+//!
+//! Implement [`Visitor`] (or [`VisitorMut`], to change nodes in place) and override the methods for
+//! the nodes you're interested in.  Every method walks into the node's children by default; an
+//! override that still wants that can call the matching `walk_` function.
+
+use std::borrow::Cow;
+
+use crate::{
+    parser::{
+        block::Block,
+        headers::Header,
+        images::Image,
+        links::{AutoLink, Link, LinkDefinition},
+        paragraphs::Paragraph,
+        util::MarkdownText,
+    },
+    span::Span,
+    Markdown,
+};
+
+pub trait Visitor<'source> {
+    fn visit_block(&mut self, block: &Block<'source>) {
+        walk_block(self, block)
+    }
+
+    fn visit_header(&mut self, header: &Header<'source>) {
+        walk_inlines(self, header.text())
+    }
+
+    fn visit_paragraph(&mut self, paragraph: &Paragraph<'source>) {
+        walk_inlines(self, paragraph.text())
+    }
+
+    fn visit_link_definition(&mut self, _definition: &LinkDefinition<'source>) {}
+
+    /// Called on every piece of inline content before the methods for each kind of it
+    fn visit_inline(&mut self, inline: &MarkdownText<'source>) {
+        walk_inline(self, inline)
+    }
+
+    fn visit_link(&mut self, link: &Link<'source>) {
+        walk_inlines(self, link.link_text())
+    }
+
+    fn visit_image(&mut self, _image: &Image<'source>) {}
+
+    fn visit_auto_link(&mut self, _link: &AutoLink<'source>) {}
+
+    /// Plain text, which can still have emphasis markers in it
+    fn visit_text(&mut self, _text: &str, _span: Span) {}
+
+    fn visit_code(&mut self, _code: &str, _span: Span) {}
+}
+
+pub fn walk_block<'source, V>(visitor: &mut V, block: &Block<'source>)
+where
+    V: Visitor<'source> + ?Sized,
+{
+    match block {
+        Block::Paragraph(p) => visitor.visit_paragraph(p),
+        Block::Heading(h) => visitor.visit_header(h),
+        Block::LinkDefinition(d) => visitor.visit_link_definition(d),
+        Block::Comment(..) | Block::Toc(_) | Block::Separator(..) => {}
+    }
+}
+
+pub fn walk_inlines<'source, V>(visitor: &mut V, text: &[MarkdownText<'source>])
+where
+    V: Visitor<'source> + ?Sized,
+{
+    for t in text.iter() {
+        visitor.visit_inline(t);
+    }
+}
+
+pub fn walk_inline<'source, V>(visitor: &mut V, inline: &MarkdownText<'source>)
+where
+    V: Visitor<'source> + ?Sized,
+{
+    match inline {
+        MarkdownText::Text(text, span) => visitor.visit_text(text, *span),
+        MarkdownText::Image(image) => visitor.visit_image(image),
+        MarkdownText::Link(link) => visitor.visit_link(link),
+        MarkdownText::AutoLink(link) => visitor.visit_auto_link(link),
+        MarkdownText::SoftBreak(_) => {}
+        MarkdownText::Code { code, span } => visitor.visit_code(code, *span),
+    }
+}
+
+/// Like [`Visitor`], but nodes can be changed as they're visited.
+pub trait VisitorMut<'source> {
+    fn visit_block(&mut self, block: &mut Block<'source>) {
+        walk_block_mut(self, block)
+    }
+
+    fn visit_header(&mut self, header: &mut Header<'source>) {
+        walk_inlines_mut(self, header.text_mut())
+    }
+
+    fn visit_paragraph(&mut self, paragraph: &mut Paragraph<'source>) {
+        walk_inlines_mut(self, paragraph.text_mut())
+    }
+
+    fn visit_link_definition(&mut self, _definition: &mut LinkDefinition<'source>) {}
+
+    /// Called on every piece of inline content before the methods for each kind of it, so this is
+    /// the place to replace one kind of node with another
+    fn visit_inline(&mut self, inline: &mut MarkdownText<'source>) {
+        walk_inline_mut(self, inline)
+    }
+
+    fn visit_link(&mut self, link: &mut Link<'source>) {
+        walk_inlines_mut(self, link.link_text_mut())
+    }
+
+    fn visit_image(&mut self, _image: &mut Image<'source>) {}
+
+    fn visit_auto_link(&mut self, _link: &mut AutoLink<'source>) {}
+
+    fn visit_text(&mut self, _text: &mut Cow<'source, str>, _span: Span) {}
+
+    fn visit_code(&mut self, _code: &mut Cow<'source, str>, _span: Span) {}
+}
+
+pub fn walk_block_mut<'source, V>(visitor: &mut V, block: &mut Block<'source>)
+where
+    V: VisitorMut<'source> + ?Sized,
+{
+    match block {
+        Block::Paragraph(p) => visitor.visit_paragraph(p),
+        Block::Heading(h) => visitor.visit_header(h),
+        Block::LinkDefinition(d) => visitor.visit_link_definition(d),
+        Block::Comment(..) | Block::Toc(_) | Block::Separator(..) => {}
+    }
+}
+
+pub fn walk_inlines_mut<'source, V>(visitor: &mut V, text: &mut [MarkdownText<'source>])
+where
+    V: VisitorMut<'source> + ?Sized,
+{
+    for t in text.iter_mut() {
+        visitor.visit_inline(t);
+    }
+}
+
+pub fn walk_inline_mut<'source, V>(visitor: &mut V, inline: &mut MarkdownText<'source>)
+where
+    V: VisitorMut<'source> + ?Sized,
+{
+    match inline {
+        MarkdownText::Text(text, span) => visitor.visit_text(text, *span),
+        MarkdownText::Image(image) => visitor.visit_image(image),
+        MarkdownText::Link(link) => visitor.visit_link(link),
+        MarkdownText::AutoLink(link) => visitor.visit_auto_link(link),
+        MarkdownText::SoftBreak(_) => {}
+        MarkdownText::Code { code, span } => visitor.visit_code(code, *span),
+    }
+}
+
+impl<'source> Markdown<'source> {
+    /// Visit every block in this document, in order.
+    pub fn visit<V: Visitor<'source>>(&self, visitor: &mut V) {
+        for block in self.blocks.iter() {
+            visitor.visit_block(block);
+        }
+    }
+
+    /// Visit every block in this document, in order, letting `visitor` change them.
+    pub fn visit_mut<V: VisitorMut<'source>>(&mut self, visitor: &mut V) {
+        for block in self.blocks.iter_mut() {
+            visitor.visit_block(block);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{parser::links::LinkRef, AsText};
+
+    #[test]
+    fn collect_links() {
+        #[derive(Default)]
+        struct Targets(Vec<String>);
+
+        impl<'source> Visitor<'source> for Targets {
+            fn visit_link(&mut self, link: &Link<'source>) {
+                if let LinkRef::Inline(target) = link.link_ref() {
+                    self.0.push(target.to_string());
+                }
+                walk_inlines(self, link.link_text());
+            }
+
+            fn visit_auto_link(&mut self, link: &AutoLink<'source>) {
+                self.0.push(link.target().to_string());
+            }
+        }
+
+        let md = Markdown::parse("# [Home](/)\n\nSee [a [b](/b)](/a) or <https://c.d>.\n");
+        let mut targets = Targets::default();
+        md.visit(&mut targets);
+        assert_eq!(targets.0, ["/", "/a", "/b", "https://c.d"]);
+    }
+
+    #[test]
+    fn rewrite_in_place() {
+        struct Rewrite;
+
+        impl<'source> VisitorMut<'source> for Rewrite {
+            fn visit_link(&mut self, link: &mut Link<'source>) {
+                if link.link_ref() == &LinkRef::Inline("/old".into()) {
+                    *link.link_ref_mut() = LinkRef::Inline("/new".into());
+                }
+                walk_inlines_mut(self, link.link_text_mut());
+            }
+
+            fn visit_text(&mut self, text: &mut Cow<'source, str>, _span: Span) {
+                if text == "TODO" {
+                    *text = "done".into();
+                }
+            }
+
+            fn visit_code(&mut self, code: &mut Cow<'source, str>, _span: Span) {
+                *code = code.replace("old", "new").into();
+            }
+        }
+
+        let mut md = Markdown::parse("TODO: [TODO](/old) and [`old()`](/other)");
+        md.visit_mut(&mut Rewrite);
+        let mut output = vec![];
+        md.write_as_text(&mut output).unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "TODO: [done](/new) and [`new()`](/other)"
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! Problems found while parsing a document.  Parsing never fails, so these are reported
+//! alongside the document instead.
+
+use std::fmt;
+
+use crate::{
+    format::display_width,
+    parser,
+    span::{LineIndex, Locate, Position, Span},
+};
+
+/// Something questionable in a document, like text that didn't parse as markdown.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// What was wrong, e.g. "couldn't parse this as markdown, so it was kept as text"
+    pub message: String,
+    /// The byte offset of the problem, in the text being parsed
+    pub offset: usize,
+    /// The line and column of the problem
+    pub position: Position,
+    /// What was being parsed, from the outermost to the innermost, e.g. `["markdown text",
+    /// "block", "inline link"]`
+    pub context: Vec<&'static str>,
+    /// The line the problem is on, with a caret under it
+    pub snippet: String,
+}
+
+impl Warning {
+    pub(crate) fn new(
+        message: impl Into<String>,
+        offset: usize,
+        context: Vec<&'static str>,
+        line_index: &LineIndex,
+    ) -> Self {
+        let position = line_index.position(offset);
+
+        let line = line_index.line(position.line).unwrap_or_default();
+        let before: String = line.chars().take(position.column - 1).collect();
+        let number = position.line.to_string();
+        let gutter = " ".repeat(number.len());
+        let snippet = format!(
+            "{gutter} |\n{number} | {line}\n{gutter} | {}^",
+            " ".repeat(display_width(&before))
+        );
+
+        Self {
+            message: message.into(),
+            offset,
+            position,
+            context,
+            snippet,
+        }
+    }
+
+    /// A warning for where `source` stopped parsing as markdown.
+    pub(crate) fn unparsed(
+        error: parser::Error<&str>,
+        source: &str,
+        line_index: &LineIndex,
+    ) -> Self {
+        let mut span = Span::of(error.input);
+        span.locate(source);
+        let mut context = error.context;
+        context.reverse();
+        Self::new(
+            "couldn't parse this as markdown, so it was kept as text",
+            span.start.min(source.len()),
+            context,
+            line_index,
+        )
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.position, self.message)?;
+        if !self.context.is_empty() {
+            write!(f, " (in {})", self.context.join(" > "))?;
+        }
+        write!(f, "\n{}", self.snippet)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn snippet() {
+        let source = "# Title\n\nSee [the 文档](x";
+        let warning = Warning::unparsed(
+            parser::Error {
+                input: &source[source.len() - 1..],
+                kind: winnow::error::ErrorKind::Tag,
+                context: vec!["inline link", "block", "markdown text"],
+            },
+            source,
+            &LineIndex::new(source),
+        );
+        assert_eq!(warning.offset, source.len() - 1);
+        assert_eq!(
+            warning.position,
+            Position {
+                line: 3,
+                column: 14
+            }
+        );
+        assert_eq!(warning.context, ["markdown text", "block", "inline link"]);
+        assert_eq!(
+            warning.to_string(),
+            "3:14: couldn't parse this as markdown, so it was kept as text \
+             (in markdown text > block > inline link)\n  |\n\
+             3 | See [the 文档](x\n  |                ^"
+        );
+    }
+
+    #[test]
+    fn empty() {
+        let md = crate::Markdown::parse("");
+        assert!(md.blocks.is_empty());
+        assert!(md.warnings().is_empty());
+    }
+}